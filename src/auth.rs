@@ -0,0 +1,31 @@
+use hyper::HeaderMap;
+
+use crate::{
+    constants::{API_KEYS, AUTH_KEY},
+    error::{Error, Result},
+};
+
+/// Checks the `Authorization` header against the configured keys and returns
+/// the name of the matching key. If no keys are configured, every request is
+/// allowed.
+///
+/// `AUTH_KEY` has no name, so its clients are told apart by their IP.
+pub fn authenticate(headers: &HeaderMap) -> Result<Option<&'static str>> {
+    if AUTH_KEY.is_none() && API_KEYS.is_empty() {
+        return Ok(None);
+    }
+
+    let client_auth = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(Error::Unauthorized)?;
+
+    if AUTH_KEY.as_deref() == Some(client_auth) {
+        return Ok(None);
+    }
+
+    API_KEYS
+        .get(client_auth)
+        .map(|name| Some(name.as_str()))
+        .ok_or(Error::Unauthorized)
+}
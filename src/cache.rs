@@ -56,6 +56,27 @@ impl ImageCache {
         self.0.get(identifier).map(|image| image.0.clone())
     }
 
+    /// Returns the number of cached images and their total size in bytes.
+    #[must_use]
+    pub fn stats(&self) -> (usize, usize) {
+        self.0.iter().fold((0, 0), |(entries, bytes), image| {
+            (entries + 1, bytes + image.0.len())
+        })
+    }
+
+    /// Verifies that the cache accepts writes by storing and removing a
+    /// sentinel entry.
+    #[must_use]
+    pub fn probe(&self) -> bool {
+        const KEY: &str = "healthcheck";
+
+        self.0.insert(KEY.to_string(), (Vec::new(), Instant::now()));
+        let stored = self.0.contains_key(KEY);
+        self.0.remove(KEY);
+
+        stored
+    }
+
     #[must_use]
     pub fn insert(&self, image: Vec<u8>) -> String {
         let counter = self.1.fetch_add(1, Ordering::SeqCst);
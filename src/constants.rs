@@ -1,20 +1,155 @@
-use std::{collections::HashMap, env::var};
+use std::{
+    collections::HashMap,
+    env::var,
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 
 use ab_glyph::FontVec;
-use image::{load_from_memory, RgbImage, RgbaImage};
+use image::{load_from_memory, ImageFormat, RgbImage, RgbaImage};
 use lazy_static::lazy_static;
 
+use crate::{
+    emoji::Sprites,
+    layout::{self, Layout},
+};
+
 lazy_static! {
     pub static ref PORT: u16 = var("PORT")
         .unwrap_or_else(|_| String::from("3000"))
         .parse::<u16>()
         .unwrap();
+    /// Number of threads rendering images, defaults to the number of CPUs
+    pub static ref RENDER_THREADS: usize = var("RENDER_THREADS").map_or_else(
+        |_| std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        |threads| {
+            threads
+                .parse::<NonZeroUsize>()
+                .expect("RENDER_THREADS must be at least 1")
+                .get()
+        },
+    );
+    /// Number of render jobs that may wait for a thread before requests are
+    /// rejected
+    pub static ref RENDER_QUEUE: usize = var("RENDER_QUEUE")
+        .unwrap_or_else(|_| String::from("32"))
+        .parse::<usize>()
+        .unwrap();
+    /// Number of background jobs rendering at once
+    pub static ref JOB_CONCURRENCY: usize = var("JOB_CONCURRENCY")
+        .unwrap_or_else(|_| String::from("4"))
+        .parse::<NonZeroUsize>()
+        .expect("JOB_CONCURRENCY must be at least 1")
+        .get();
+    /// Number of background jobs that may be queued or running before new
+    /// jobs are rejected
+    pub static ref JOB_QUEUE: usize = var("JOB_QUEUE")
+        .unwrap_or_else(|_| String::from("64"))
+        .parse::<usize>()
+        .unwrap();
+    /// Work an imageops pipeline may do, in megapixels touched by a simple
+    /// per-pixel operation
+    pub static ref PIPELINE_BUDGET: u64 = var("PIPELINE_BUDGET")
+        .unwrap_or_else(|_| String::from("200"))
+        .parse::<u64>()
+        .unwrap()
+        * 1_000_000;
+    /// Largest width and height of images downloaded by routes
+    pub static ref MAX_IMAGE_DIMENSION: u32 = var("MAX_IMAGE_DIMENSION")
+        .unwrap_or_else(|_| String::from("2000"))
+        .parse::<u32>()
+        .unwrap();
+    /// Memory that decoding a downloaded image may use, in bytes
+    pub static ref MAX_IMAGE_BYTES: u64 = var("MAX_IMAGE_BYTES")
+        .unwrap_or_else(|_| String::from("67108864"))
+        .parse::<u64>()
+        .unwrap();
+    /// Formats of images downloaded by routes, as comma-separated extensions
+    pub static ref IMAGE_FORMATS: Vec<ImageFormat> = var("IMAGE_FORMATS")
+        .unwrap_or_else(|_| String::from("png,jpeg"))
+        .split(',')
+        .filter(|format| !format.is_empty())
+        .map(|format| {
+            ImageFormat::from_extension(format.trim()).expect("invalid IMAGE_FORMATS entry")
+        })
+        .collect();
+    /// Time to wait for an image host to send a downloaded image
+    pub static ref UPSTREAM_TIMEOUT: Duration = Duration::from_secs(
+        var("UPSTREAM_TIMEOUT")
+            .unwrap_or_else(|_| String::from("10"))
+            .parse::<u64>()
+            .unwrap(),
+    );
+    /// Time to wait for in-flight requests when shutting down
+    pub static ref SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(
+        var("SHUTDOWN_TIMEOUT")
+            .unwrap_or_else(|_| String::from("30"))
+            .parse::<u64>()
+            .unwrap(),
+    );
     pub static ref AUTH_KEY: Option<String> = var("AUTH_KEY").ok();
+    /// Named API keys in the format `name:key,name:key`, mapped from key to name
+    pub static ref API_KEYS: HashMap<String, String> = var("API_KEYS")
+        .map(|keys| {
+            keys.split(',')
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    let (name, key) = entry.split_once(':').expect("invalid API_KEYS entry");
+                    (key.to_string(), name.to_string())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    /// Proxies whose `X-Forwarded-For` header is trusted
+    pub static ref TRUSTED_PROXIES: Vec<IpAddr> = var("TRUSTED_PROXIES")
+        .map(|proxies| {
+            proxies
+                .split(',')
+                .filter(|proxy| !proxy.is_empty())
+                .map(|proxy| proxy.trim().parse().expect("invalid TRUSTED_PROXIES entry"))
+                .collect()
+        })
+        .unwrap_or_default();
+    /// Ratelimit rules in the format `[key@]route=capacity/seconds`
+    pub static ref RATELIMITS: Option<String> = var("RATELIMITS").ok();
     pub static ref EXTERNAL_URL: String =
         var("EXTERNAL_URL").unwrap_or(format!("http://localhost:{}", *PORT));
     pub static ref TRAVITIA_FONT: FontVec =
         FontVec::try_from_vec(include_bytes!("../assets/fonts/MergedNoKern.otf").to_vec())
             .expect("could not load font");
+    pub static ref TRAV_MEDIUM_FONT: FontVec =
+        FontVec::try_from_vec(include_bytes!("../assets/fonts/TravMedium.otf").to_vec())
+            .expect("could not load font");
+    pub static ref CAVIAR_DREAMS_FONT: FontVec =
+        FontVec::try_from_vec(include_bytes!("../assets/fonts/CaviarDreams.ttf").to_vec())
+            .expect("could not load font");
+    pub static ref OPEN_SANS_EMOJI_FONT: FontVec =
+        FontVec::try_from_vec(include_bytes!("../assets/fonts/OpenSansEmoji.ttf").to_vec())
+            .expect("could not load font");
+    /// The Travitia font followed by the fonts that characters it has no
+    /// glyph for are drawn with, in order
+    pub static ref TRAVITIA_FONTS: [&'static FontVec; 4] = [
+        &TRAVITIA_FONT,
+        &TRAV_MEDIUM_FONT,
+        &CAVIAR_DREAMS_FONT,
+        &OPEN_SANS_EMOJI_FONT,
+    ];
+    /// The TravMedium font followed by its fallbacks
+    pub static ref TRAV_MEDIUM_FONTS: [&'static FontVec; 3] =
+        [&TRAV_MEDIUM_FONT, &CAVIAR_DREAMS_FONT, &OPEN_SANS_EMOJI_FONT];
+    /// The CaviarDreams font followed by its fallbacks
+    pub static ref CAVIAR_DREAMS_FONTS: [&'static FontVec; 3] =
+        [&CAVIAR_DREAMS_FONT, &TRAV_MEDIUM_FONT, &OPEN_SANS_EMOJI_FONT];
+    /// The OpenSansEmoji font followed by its fallbacks
+    pub static ref OPEN_SANS_EMOJI_FONTS: [&'static FontVec; 3] =
+        [&OPEN_SANS_EMOJI_FONT, &TRAV_MEDIUM_FONT, &CAVIAR_DREAMS_FONT];
+    /// Color emoji drawn in place of characters, see [`crate::emoji::Sprites`]
+    pub static ref EMOJI: Sprites = Sprites::load();
+    /// Profile layouts by name, see [`crate::layout::load`]
+    pub static ref LAYOUTS: HashMap<String, Layout> =
+        layout::load(include_bytes!("../assets/layouts/default.json"));
     pub static ref PROFILE_DARK: RgbaImage = load_from_memory(include_bytes!(
         "../assets/images/out/profile_overlay_dark.png"
     ))
@@ -358,3 +493,37 @@ lazy_static! {
             .into_rgb8()
     ];
 }
+
+/// Number of decoded embedded assets, zero until [`load_assets`] finished.
+static LOADED_ASSETS: AtomicUsize = AtomicUsize::new(0);
+
+/// Decodes all embedded assets. Decoding errors abort the process, so once
+/// this returns every asset is usable.
+pub fn load_assets() {
+    lazy_static::initialize(&TRAVITIA_FONTS);
+    lazy_static::initialize(&TRAV_MEDIUM_FONTS);
+    lazy_static::initialize(&CAVIAR_DREAMS_FONTS);
+    lazy_static::initialize(&OPEN_SANS_EMOJI_FONTS);
+    lazy_static::initialize(&PROFILE_DARK);
+    lazy_static::initialize(&PROFILE_LIGHT);
+    lazy_static::initialize(&DEFAULT_PROFILE);
+    lazy_static::initialize(&EMOJI);
+
+    let count = 3
+        + TRAVITIA_FONTS.len()
+        + EMOJI.len()
+        + LAYOUTS.len()
+        + RACES.len()
+        + CLASSES.len()
+        + GUILD_RANKS.len()
+        + ITEM_TYPES.len()
+        + BADGES.len()
+        + ADVENTURES.len();
+
+    LOADED_ASSETS.store(count, Ordering::Release);
+}
+
+/// Returns the number of decoded assets, or zero if they are still loading.
+pub fn loaded_assets() -> usize {
+    LOADED_ASSETS.load(Ordering::Acquire)
+}
@@ -0,0 +1,37 @@
+use std::io::Cursor;
+
+use image::{
+    io::{Limits, Reader},
+    DynamicImage, ImageError,
+};
+
+use crate::{
+    constants::{IMAGE_FORMATS, MAX_IMAGE_BYTES, MAX_IMAGE_DIMENSION},
+    error::{Error, Result},
+};
+
+/// Decodes an image that a route downloaded. Only `IMAGE_FORMATS` are
+/// accepted, and decoding stops early if the image is larger than
+/// `MAX_IMAGE_DIMENSION` or needs more than `MAX_IMAGE_BYTES` of memory.
+pub fn decode(buf: &[u8]) -> Result<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(*MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(*MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(*MAX_IMAGE_BYTES);
+
+    let mut reader = Reader::new(Cursor::new(buf)).with_guessed_format()?;
+
+    if !reader
+        .format()
+        .is_some_and(|format| IMAGE_FORMATS.contains(&format))
+    {
+        return Err(Error::UnsupportedImageFormat);
+    }
+
+    reader.limits(limits);
+
+    reader.decode().map_err(|e| match e {
+        ImageError::Limits(_) => Error::ImageTooLarge,
+        e => Error::Image(e),
+    })
+}
@@ -0,0 +1,274 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    env::var,
+    ffi::OsStr,
+    fs,
+    io::Cursor,
+    sync::{Arc, OnceLock},
+};
+
+use dashmap::DashMap;
+use image::{
+    imageops::{resize, FilterType},
+    io::Reader,
+    load_from_memory_with_format, DynamicImage, ImageFormat, RgbaImage,
+};
+
+/// Ranges of characters that are drawn as emoji by default, the ones with the
+/// `Emoji_Presentation` property as of Unicode 15.1.
+const EMOJI_PRESENTATION: &[(char, char)] = &[
+    ('\u{231a}', '\u{231b}'),
+    ('\u{23e9}', '\u{23ec}'),
+    ('\u{23f0}', '\u{23f0}'),
+    ('\u{23f3}', '\u{23f3}'),
+    ('\u{25fd}', '\u{25fe}'),
+    ('\u{2614}', '\u{2615}'),
+    ('\u{2648}', '\u{2653}'),
+    ('\u{267f}', '\u{267f}'),
+    ('\u{2693}', '\u{2693}'),
+    ('\u{26a1}', '\u{26a1}'),
+    ('\u{26aa}', '\u{26ab}'),
+    ('\u{26bd}', '\u{26be}'),
+    ('\u{26c4}', '\u{26c5}'),
+    ('\u{26ce}', '\u{26ce}'),
+    ('\u{26d4}', '\u{26d4}'),
+    ('\u{26ea}', '\u{26ea}'),
+    ('\u{26f2}', '\u{26f3}'),
+    ('\u{26f5}', '\u{26f5}'),
+    ('\u{26fa}', '\u{26fa}'),
+    ('\u{26fd}', '\u{26fd}'),
+    ('\u{2705}', '\u{2705}'),
+    ('\u{270a}', '\u{270b}'),
+    ('\u{2728}', '\u{2728}'),
+    ('\u{274c}', '\u{274c}'),
+    ('\u{274e}', '\u{274e}'),
+    ('\u{2753}', '\u{2755}'),
+    ('\u{2757}', '\u{2757}'),
+    ('\u{2795}', '\u{2797}'),
+    ('\u{27b0}', '\u{27b0}'),
+    ('\u{27bf}', '\u{27bf}'),
+    ('\u{2b1b}', '\u{2b1c}'),
+    ('\u{2b50}', '\u{2b50}'),
+    ('\u{2b55}', '\u{2b55}'),
+    ('\u{1f004}', '\u{1f004}'),
+    ('\u{1f0cf}', '\u{1f0cf}'),
+    ('\u{1f18e}', '\u{1f18e}'),
+    ('\u{1f191}', '\u{1f19a}'),
+    ('\u{1f1e6}', '\u{1f1ff}'),
+    ('\u{1f201}', '\u{1f201}'),
+    ('\u{1f21a}', '\u{1f21a}'),
+    ('\u{1f22f}', '\u{1f22f}'),
+    ('\u{1f232}', '\u{1f236}'),
+    ('\u{1f238}', '\u{1f23a}'),
+    ('\u{1f250}', '\u{1f251}'),
+    ('\u{1f300}', '\u{1f320}'),
+    ('\u{1f32d}', '\u{1f335}'),
+    ('\u{1f337}', '\u{1f37c}'),
+    ('\u{1f37e}', '\u{1f393}'),
+    ('\u{1f3a0}', '\u{1f3ca}'),
+    ('\u{1f3cf}', '\u{1f3d3}'),
+    ('\u{1f3e0}', '\u{1f3f0}'),
+    ('\u{1f3f4}', '\u{1f3f4}'),
+    ('\u{1f3f8}', '\u{1f43e}'),
+    ('\u{1f440}', '\u{1f440}'),
+    ('\u{1f442}', '\u{1f4fc}'),
+    ('\u{1f4ff}', '\u{1f53d}'),
+    ('\u{1f54b}', '\u{1f54e}'),
+    ('\u{1f550}', '\u{1f567}'),
+    ('\u{1f57a}', '\u{1f57a}'),
+    ('\u{1f595}', '\u{1f596}'),
+    ('\u{1f5a4}', '\u{1f5a4}'),
+    ('\u{1f5fb}', '\u{1f64f}'),
+    ('\u{1f680}', '\u{1f6c5}'),
+    ('\u{1f6cc}', '\u{1f6cc}'),
+    ('\u{1f6d0}', '\u{1f6d2}'),
+    ('\u{1f6d5}', '\u{1f6d7}'),
+    ('\u{1f6dc}', '\u{1f6df}'),
+    ('\u{1f6eb}', '\u{1f6ec}'),
+    ('\u{1f6f4}', '\u{1f6fc}'),
+    ('\u{1f7e0}', '\u{1f7eb}'),
+    ('\u{1f7f0}', '\u{1f7f0}'),
+    ('\u{1f90c}', '\u{1f93a}'),
+    ('\u{1f93c}', '\u{1f945}'),
+    ('\u{1f947}', '\u{1f9ff}'),
+    ('\u{1fa70}', '\u{1fa7c}'),
+    ('\u{1fa80}', '\u{1fa88}'),
+    ('\u{1fa90}', '\u{1fabd}'),
+    ('\u{1fabf}', '\u{1fac5}'),
+    ('\u{1face}', '\u{1fadb}'),
+    ('\u{1fae0}', '\u{1fae8}'),
+    ('\u{1faf0}', '\u{1faf8}'),
+];
+
+fn has_emoji_presentation(c: char) -> bool {
+    EMOJI_PRESENTATION
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Color emoji PNG sprites, named after their codepoints in lowercase hex
+/// joined by dashes, like `1f44b.png` or `1f468-200d-1f469-200d-1f467.png`.
+#[derive(Default)]
+pub struct Sprites {
+    sprites: HashMap<String, Sprite>,
+    /// Number of codepoints in the longest sequence.
+    longest: usize,
+}
+
+impl Sprites {
+    /// Loads every `*.png` file in `EMOJI_DIR`. Without it, no emoji are
+    /// drawn as sprites.
+    #[must_use]
+    pub fn load() -> Self {
+        let Ok(dir) = var("EMOJI_DIR") else {
+            return Self::default();
+        };
+
+        let mut sprites = HashMap::new();
+
+        for entry in fs::read_dir(dir).expect("could not read EMOJI_DIR") {
+            let path = entry.expect("could not read EMOJI_DIR").path();
+
+            if path.extension() != Some(OsStr::new("png")) {
+                continue;
+            }
+
+            let name = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .expect("invalid emoji file name")
+                .to_lowercase();
+            let data = fs::read(&path).expect("could not read emoji");
+
+            // Only the header is checked, sprites are decoded on first use
+            Reader::with_format(Cursor::new(&data), ImageFormat::Png)
+                .into_dimensions()
+                .unwrap_or_else(|e| panic!("invalid emoji {}: {e}", path.display()));
+
+            sprites.insert(
+                name,
+                Sprite {
+                    data,
+                    image: OnceLock::new(),
+                    scaled: DashMap::new(),
+                },
+            );
+        }
+
+        let longest = sprites
+            .keys()
+            .map(|name| name.split('-').count())
+            .max()
+            .unwrap_or_default();
+
+        Self { sprites, longest }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    /// Finds the longest emoji sequence at the start of `text`. Variation
+    /// selectors are optional in file names.
+    ///
+    /// A single character only matches if it is drawn as emoji by default or
+    /// followed by U+FE0F, and never if followed by U+FE0E, so that symbols
+    /// like © stay text.
+    ///
+    /// Returns the length of the sequence in bytes and its sprite.
+    #[must_use]
+    pub fn find(&self, text: &str) -> Option<(usize, &Sprite)> {
+        // One more character to see the variation selector after a sequence
+        let chars: Vec<char> = text.chars().take(self.longest + 1).collect();
+
+        // Plain text only turns into an emoji as part of a sequence, like
+        // keycaps
+        if chars.first()?.is_ascii() && !matches!(chars.get(1), Some('\u{fe0f}' | '\u{20e3}')) {
+            return None;
+        }
+
+        (1..=chars.len().min(self.longest)).rev().find_map(|count| {
+            let sequence = &chars[..count];
+
+            if let [c] = sequence {
+                match chars.get(count) {
+                    Some('\u{fe0f}') => {}
+                    Some('\u{fe0e}') => return None,
+                    _ if !has_emoji_presentation(*c) => return None,
+                    _ => {}
+                }
+            }
+
+            let name = |skip_selectors: bool| {
+                sequence
+                    .iter()
+                    .filter(|&&c| !skip_selectors || c != '\u{fe0f}')
+                    .map(|&c| format!("{:x}", u32::from(c)))
+                    .collect::<Vec<_>>()
+                    .join("-")
+            };
+
+            let sprite = self
+                .sprites
+                .get(&name(false))
+                .or_else(|| self.sprites.get(&name(true)))?;
+
+            // A trailing variation selector belongs to the emoji
+            let mut len: usize = sequence.iter().map(|c| c.len_utf8()).sum();
+            if chars.get(count) == Some(&'\u{fe0f}') {
+                len += '\u{fe0f}'.len_utf8();
+            }
+
+            Some((len, sprite))
+        })
+    }
+}
+
+/// A single emoji sprite. It is decoded once on first use, and every size it
+/// is drawn at is kept so that it only gets scaled once.
+pub struct Sprite {
+    /// PNG data, with a header that was checked when loading.
+    data: Vec<u8>,
+    /// The decoded sprite, or `None` if the data turned out to be broken.
+    image: OnceLock<Option<RgbaImage>>,
+    scaled: DashMap<u32, Arc<RgbaImage>>,
+}
+
+impl Sprite {
+    /// Returns the sprite scaled to `size`x`size` pixels, or `None` if it
+    /// cannot be decoded.
+    #[must_use]
+    pub fn render(&self, size: u32) -> Option<Arc<RgbaImage>> {
+        let size = size.max(1);
+        let image = self
+            .image
+            .get_or_init(|| {
+                load_from_memory_with_format(&self.data, ImageFormat::Png)
+                    .ok()
+                    .map(DynamicImage::into_rgba8)
+            })
+            .as_ref()?;
+
+        Some(
+            self.scaled
+                .entry(size)
+                .or_insert_with(|| Arc::new(resize(image, size, size, FilterType::Triangle)))
+                .clone(),
+        )
+    }
+}
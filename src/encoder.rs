@@ -2,7 +2,7 @@ use std::ops::Deref;
 
 use image::{
     codecs::png::{CompressionType, FilterType, PngEncoder},
-    ImageBuffer, ImageEncoder, ImageError, Pixel, PixelWithColorType,
+    DynamicImage, ImageBuffer, ImageEncoder, ImageError, Pixel, PixelWithColorType,
 };
 
 pub fn encode_png<P, Container>(img: &ImageBuffer<P, Container>) -> Result<Vec<u8>, ImageError>
@@ -15,3 +15,14 @@ where
     encoder.write_image(img, img.width(), img.height(), P::COLOR_TYPE)?;
     Ok(buf)
 }
+
+/// Encodes 8-bit grayscale and color images as-is, anything else as RGBA.
+pub fn encode_dynamic_png(img: &DynamicImage) -> Result<Vec<u8>, ImageError> {
+    match img {
+        DynamicImage::ImageLuma8(buf) => encode_png(buf),
+        DynamicImage::ImageLumaA8(buf) => encode_png(buf),
+        DynamicImage::ImageRgb8(buf) => encode_png(buf),
+        DynamicImage::ImageRgba8(buf) => encode_png(buf),
+        img => encode_png(&img.to_rgba8()),
+    }
+}
@@ -1,4 +1,9 @@
+use std::time::Duration;
+
 use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+
+use crate::constants::{IMAGE_FORMATS, MAX_IMAGE_DIMENSION, UPSTREAM_TIMEOUT};
 
 #[derive(Debug)]
 pub enum Error {
@@ -13,6 +18,19 @@ pub enum Error {
     Io(std::io::Error),
     InvalidImageHost,
     Ratelimited,
+    UpstreamStatus(u16),
+    UpstreamTimeout,
+    TooManyRequests(Duration),
+    Unauthorized,
+    Overloaded(Duration),
+    InvalidParameter(String),
+    InvalidValue {
+        field: String,
+        valid: Vec<&'static str>,
+    },
+    BudgetExceeded,
+    ImageTooLarge,
+    UnsupportedImageFormat,
 }
 
 impl From<hyper::Error> for Error {
@@ -58,88 +76,205 @@ impl From<std::io::Error> for Error {
 }
 
 impl Error {
+    /// Returns the name of the variant, used for logging.
     #[must_use]
-    pub fn into_response(&self) -> Response<Body> {
+    pub const fn kind(&self) -> &'static str {
         match self {
-            Self::Svg(err) => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(format!(
-                    "{{\"status\": \"error\", \"reason\": \"invalid SVG data\", \"detail\": \"{err}\"}}"
-                ))).unwrap()
-            }
-            Self::Hyper(err) => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(format!(
-                    "{{\"status\": \"error\", \"reason\": \"download error\", \"detail\": \"{err}\"}}"
-                )))
-                .unwrap()
-            }
-            Self::Image(err) => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(format!(
-                    "{{\"status\": \"error\", \"reason\": \"invalid image data\", \"detail\": \"{err}\"}}"
-                )))
-                .unwrap()
-            }
-            Self::Io(err) => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(format!(
-                    "{{\"status\": \"error\", \"reason\": \"invalid image data\", \"detail\": \"{err}\"}}"
-                )))
-                .unwrap()
-            }
-            Self::Json(err) => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(format!(
-                    "{{\"status\": \"error\", \"reason\": \"invalid request JSON data\", \"detail\": \"{err}\"}}"
-                )))
-                .unwrap()
+            Self::Image(_) => "Image",
+            Self::Hyper(_) => "Hyper",
+            Self::Http(_) => "Http",
+            Self::Svg(_) => "Svg",
+            Self::Json(_) => "Json",
+            Self::PayloadTooBig => "PayloadTooBig",
+            Self::ImageTooSmall => "ImageTooSmall",
+            Self::InvalidUri(_) => "InvalidUri",
+            Self::Io(_) => "Io",
+            Self::InvalidImageHost => "InvalidImageHost",
+            Self::Ratelimited => "Ratelimited",
+            Self::UpstreamStatus(_) => "UpstreamStatus",
+            Self::UpstreamTimeout => "UpstreamTimeout",
+            Self::TooManyRequests(_) => "TooManyRequests",
+            Self::Unauthorized => "Unauthorized",
+            Self::Overloaded(_) => "Overloaded",
+            Self::InvalidParameter(_) => "InvalidParameter",
+            Self::InvalidValue { .. } => "InvalidValue",
+            Self::BudgetExceeded => "BudgetExceeded",
+            Self::ImageTooLarge => "ImageTooLarge",
+            Self::UnsupportedImageFormat => "UnsupportedImageFormat",
+        }
+    }
+
+    /// Returns a stable, machine-readable code for the error.
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::Image(_) | Self::Io(_) => "invalid_image",
+            Self::Hyper(_) => "upstream_error",
+            Self::Http(_) => "internal_error",
+            Self::Svg(_) => "invalid_svg",
+            Self::Json(_) => "invalid_json",
+            Self::PayloadTooBig => "payload_too_big",
+            Self::ImageTooSmall => "image_too_small",
+            Self::InvalidUri(_) => "invalid_uri",
+            Self::InvalidImageHost => "invalid_image_host",
+            Self::Ratelimited => "upstream_ratelimited",
+            Self::UpstreamStatus(_) => "upstream_status",
+            Self::UpstreamTimeout => "upstream_timeout",
+            Self::TooManyRequests(_) => "too_many_requests",
+            Self::Unauthorized => "unauthorized",
+            Self::Overloaded(_) => "overloaded",
+            Self::InvalidParameter(_) => "invalid_parameter",
+            Self::InvalidValue { .. } => "invalid_value",
+            Self::BudgetExceeded => "budget_exceeded",
+            Self::ImageTooLarge => "image_too_large",
+            Self::UnsupportedImageFormat => "unsupported_image_format",
+        }
+    }
+
+    #[must_use]
+    pub const fn status(&self) -> StatusCode {
+        match self {
+            Self::Json(_) | Self::InvalidUri(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::FORBIDDEN,
+            Self::PayloadTooBig => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Http(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Hyper(_) | Self::Ratelimited | Self::UpstreamStatus(_) => StatusCode::BAD_GATEWAY,
+            Self::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::Image(_)
+            | Self::Io(_)
+            | Self::Svg(_)
+            | Self::ImageTooSmall
+            | Self::InvalidImageHost
+            | Self::InvalidParameter(_)
+            | Self::InvalidValue { .. }
+            | Self::BudgetExceeded
+            | Self::ImageTooLarge
+            | Self::UnsupportedImageFormat => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    const fn reason(&self) -> &'static str {
+        match self {
+            Self::Image(_) | Self::Io(_) => "invalid image data",
+            Self::Hyper(_) | Self::UpstreamStatus(_) => "download error",
+            Self::Http(_) => "internal error",
+            Self::Svg(_) => "invalid SVG data",
+            Self::Json(_) => "invalid request JSON data",
+            Self::PayloadTooBig => "payload too big",
+            Self::ImageTooSmall => "background image too small",
+            Self::InvalidUri(_) => "invalid URI",
+            Self::InvalidImageHost => "invalid image host",
+            Self::Ratelimited => "ratelimited",
+            Self::UpstreamTimeout => "download timed out",
+            Self::TooManyRequests(_) => "too many requests",
+            Self::Unauthorized => "unauthorized",
+            Self::Overloaded(_) => "overloaded",
+            Self::InvalidParameter(_) => "invalid parameter",
+            Self::InvalidValue { .. } => "invalid value",
+            Self::BudgetExceeded => "compute budget exceeded",
+            Self::ImageTooLarge => "image too large",
+            Self::UnsupportedImageFormat => "unsupported image format",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Self::Image(err) => err.to_string(),
+            Self::Hyper(err) => err.to_string(),
+            Self::Http(err) => err.to_string(),
+            Self::Svg(err) => err.to_string(),
+            Self::Json(err) => err.to_string(),
+            Self::InvalidUri(err) => err.to_string(),
+            Self::Io(err) => err.to_string(),
+            Self::InvalidParameter(detail) => detail.clone(),
+            Self::InvalidValue { field, valid } => {
+                format!("{field} must be one of {}", valid.join(", "))
             }
+            Self::PayloadTooBig => String::from("the image must be smaller than 3 MiB"),
             Self::ImageTooSmall => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(String::from(
-                    "{\"status\": \"error\", \"reason\": \"background image too small\", \"detail\": \"background image must be at least 800x533 in size\"}",
-                )))
-                .unwrap()
+                String::from("background image must be at least 800x533 in size")
             }
             Self::InvalidImageHost => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(String::from(
-                    "{\"status\": \"error\", \"reason\": \"invalid image host\", \"detail\": \"custom backgrounds must be hosted on a trusted domain\"}",
-                )))
-                .unwrap()
+                String::from("custom backgrounds must be hosted on a trusted domain")
             }
             Self::Ratelimited => {
-                Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header("content-type", "application/json")
-                .body(Body::from(String::from(
-                    "{\"status\": \"error\", \"reason\": \"ratelimited\", \"detail\": \"the custom background image host has ratelimited or banned our IP\"}",
-                )))
-                .unwrap()
+                String::from("the custom background image host has ratelimited or banned our IP")
+            }
+            Self::UpstreamStatus(status) => format!("the image host responded with {status}"),
+            Self::UpstreamTimeout => format!(
+                "the image host did not respond within {} seconds",
+                UPSTREAM_TIMEOUT.as_secs()
+            ),
+            Self::TooManyRequests(retry_after) => {
+                format!("retry in {} seconds", retry_after_secs(*retry_after))
             }
-            _ => {
-                Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR)
-                .header("content-type", "application/json")
-                .body(Body::from(String::from("{\"status\": \"error\", \"reason\": \"unknown\", \"detail\": \"\"}")))
-                .unwrap()
+            Self::Unauthorized => String::from("missing or invalid authorization header"),
+            Self::Overloaded(_) => String::from("too many images are being rendered right now"),
+            Self::BudgetExceeded => String::from("the operations are too expensive for this image"),
+            Self::ImageTooLarge => {
+                let max = *MAX_IMAGE_DIMENSION;
+                format!("images may be at most {max}x{max} in size")
             }
+            Self::UnsupportedImageFormat => {
+                let formats = IMAGE_FORMATS
+                    .iter()
+                    .map(|format| format.extensions_str()[0])
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("supported formats are {formats}")
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn into_response(&self) -> Response<Body> {
+        let (field, valid) = match self {
+            Self::InvalidValue { field, valid } => (Some(field.as_str()), Some(valid.as_slice())),
+            _ => (None, None),
+        };
+
+        let body = ErrorBody {
+            status: "error",
+            code: self.code(),
+            reason: self.reason(),
+            detail: self.detail(),
+            field,
+            valid,
+        };
+
+        let mut response = Response::builder()
+            .status(self.status())
+            .header("content-type", "application/json");
+
+        if let Self::TooManyRequests(retry_after) | Self::Overloaded(retry_after) = self {
+            response = response.header("retry-after", retry_after_secs(*retry_after));
         }
+
+        // Serializing a struct of strings cannot fail
+        response
+            .body(Body::from(simd_json::to_string(&body).unwrap()))
+            .unwrap()
     }
 }
 
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    status: &'static str,
+    code: &'static str,
+    reason: &'static str,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    valid: Option<&'a [&'static str]>,
+}
+
+/// Rounds up, so that clients never retry too early.
+const fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil() as u64
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Instant};
 
 use bytes::Bytes;
 use hyper::{
@@ -7,8 +7,14 @@ use hyper::{
     Client, Uri,
 };
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use tokio::time::timeout;
 
-use crate::error::{Error, Result};
+use crate::{
+    constants::UPSTREAM_TIMEOUT,
+    error::{Error, Result},
+    logging::{millis, with_context},
+    metrics::METRICS,
+};
 
 const ALLOWED_HOSTS: &[&str] = &["idlerpg.xyz", "i.imgur.com", "i.postimg.cc"];
 
@@ -29,6 +35,8 @@ impl Fetcher {
         Self { client }
     }
 
+    /// Downloads an image from one of the allowed hosts. The whole download
+    /// may take at most `UPSTREAM_TIMEOUT`.
     pub async fn fetch(&self, url: &str) -> Result<Bytes> {
         let parsed_uri = Uri::from_str(url)?;
         let host = parsed_uri.host().ok_or(Error::InvalidImageHost)?;
@@ -40,16 +48,41 @@ impl Fetcher {
             return Err(Error::InvalidImageHost);
         }
 
-        let response = self.client.get(parsed_uri).await?;
+        let start = Instant::now();
+        let Ok(response) = timeout(*UPSTREAM_TIMEOUT, self.client.get(parsed_uri.clone())).await
+        else {
+            METRICS.observe_upstream(host, "timeout", start.elapsed());
+            return Err(Error::UpstreamTimeout);
+        };
+        let status = response.as_ref().map_or_else(
+            |_| String::from("error"),
+            |response| response.status().as_str().to_string(),
+        );
+        let elapsed = start.elapsed();
+        METRICS.observe_upstream(host, &status, elapsed);
+        with_context(|context| {
+            context.upstream_host = Some(host.to_string());
+            context.upstream_ms = Some(millis(elapsed));
+        });
+        let response = response?;
 
         if response.status() == 429 {
             return Err(Error::Ratelimited);
         }
 
+        if !response.status().is_success() {
+            return Err(Error::UpstreamStatus(response.status().as_u16()));
+        }
+
         let size = response.size_hint().exact();
 
         if size.is_some() && size.unwrap() < 1024 * 1024 * 3 {
-            Ok(to_bytes(response).await?)
+            let remaining = UPSTREAM_TIMEOUT.saturating_sub(start.elapsed());
+
+            timeout(remaining, to_bytes(response))
+                .await
+                .map_err(|_| Error::UpstreamTimeout)?
+                .map_err(Error::from)
         } else {
             Err(Error::PayloadTooBig)
         }
@@ -0,0 +1,6 @@
+pub mod color;
+pub mod geometry;
+pub mod mask;
+pub mod oil;
+pub mod shape;
+pub mod stylize;
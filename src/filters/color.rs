@@ -0,0 +1,67 @@
+use image::RgbaImage;
+
+/// Perceived brightness of a color, using the BT.601 weights.
+#[must_use]
+pub fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.114f32.mul_add(b, 0.299f32.mul_add(r, 0.587 * g))
+}
+
+/// Average perceived brightness of the pixels in the given rectangle, from 0
+/// to 255, or `None` if it lies outside of the image.
+#[must_use]
+pub fn average_luma(img: &RgbaImage, x: i64, y: i64, width: u32, height: u32) -> Option<f32> {
+    let clamp = |value: i64, max: u32| value.clamp(0, i64::from(max)) as u32;
+    let (left, right) = (
+        clamp(x, img.width()),
+        clamp(x + i64::from(width), img.width()),
+    );
+    let (top, bottom) = (
+        clamp(y, img.height()),
+        clamp(y + i64::from(height), img.height()),
+    );
+
+    let mut sum = 0.0f64;
+    let mut count = 0u32;
+
+    for row in top..bottom {
+        for column in left..right {
+            let pixel = img.get_pixel(column, row).0.map(f32::from);
+            sum += f64::from(luma(pixel[0], pixel[1], pixel[2]));
+            count += 1;
+        }
+    }
+
+    (count > 0).then(|| (sum / f64::from(count)) as f32)
+}
+
+/// Moves every channel away from (or towards) the luma of its pixel. A
+/// `factor` of 0 gives a grayscale image, 1 leaves the image unchanged.
+pub fn saturate(img: &mut RgbaImage, factor: f32) {
+    for pixel in img.pixels_mut() {
+        let [r, g, b, _] = pixel.0.map(f32::from);
+        let luma = luma(r, g, b);
+
+        for channel in &mut pixel.0[..3] {
+            let value = (f32::from(*channel) - luma).mul_add(factor, luma);
+            *channel = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Tints the image brown like an old photograph. `intensity` blends between
+/// the original (0) and full sepia (1).
+pub fn sepia(img: &mut RgbaImage, intensity: f32) {
+    for pixel in img.pixels_mut() {
+        let [r, g, b, _] = pixel.0.map(f32::from);
+        let toned = [
+            0.189f32.mul_add(b, 0.393f32.mul_add(r, 0.769 * g)),
+            0.168f32.mul_add(b, 0.349f32.mul_add(r, 0.686 * g)),
+            0.131f32.mul_add(b, 0.272f32.mul_add(r, 0.534 * g)),
+        ];
+
+        for (channel, toned) in pixel.0[..3].iter_mut().zip(toned) {
+            let value = (toned - f32::from(*channel)).mul_add(intensity, f32::from(*channel));
+            *channel = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use image::{
+    imageops::{blur, crop_imm, overlay, resize, FilterType},
+    Rgba, RgbaImage,
+};
+
+/// Size of the canvas that a `width`x`height` image rotated by `degrees`
+/// fits into, see [`rotate`].
+#[must_use]
+pub fn rotated_size(width: u32, height: u32, degrees: f64) -> (u32, u32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (f64::from(width), f64::from(height));
+
+    (
+        width.mul_add(cos.abs(), height * sin.abs()).round() as u32,
+        width.mul_add(sin.abs(), height * cos.abs()).round() as u32,
+    )
+}
+
+/// Rotates the image clockwise by `degrees` around its center. The canvas
+/// grows to fit the rotated image and the uncovered corners are filled with
+/// `background`. Pixels are sampled bilinearly.
+#[must_use]
+pub fn rotate(img: &RgbaImage, degrees: f64, background: Rgba<u8>) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+
+    let (width_f, height_f) = (f64::from(width), f64::from(height));
+    let (new_width, new_height) = rotated_size(width, height, degrees);
+
+    let mut target = RgbaImage::new(new_width.max(1), new_height.max(1));
+    let (center_x, center_y) = (width_f / 2.0, height_f / 2.0);
+    let (new_center_x, new_center_y) = (
+        f64::from(target.width()) / 2.0,
+        f64::from(target.height()) / 2.0,
+    );
+
+    for (x, y, pixel) in target.enumerate_pixels_mut() {
+        let dx = f64::from(x) + 0.5 - new_center_x;
+        let dy = f64::from(y) + 0.5 - new_center_y;
+
+        // Map back into the source by rotating the other way
+        let source_x = dx.mul_add(cos, dy * sin) + center_x - 0.5;
+        let source_y = dy.mul_add(cos, -dx * sin) + center_y - 0.5;
+
+        *pixel = sample(img, source_x, source_y, background);
+    }
+
+    target
+}
+
+/// Interpolates between the four pixels around `(x, y)`, treating everything
+/// outside of the image as `background`.
+fn sample(img: &RgbaImage, x: f64, y: f64, background: Rgba<u8>) -> Rgba<u8> {
+    let (left, top) = (x.floor(), y.floor());
+    let (fx, fy) = (x - left, y - top);
+
+    let pixel_at = |x: f64, y: f64| {
+        if x < 0.0 || y < 0.0 || x >= f64::from(img.width()) || y >= f64::from(img.height()) {
+            background
+        } else {
+            *img.get_pixel(x as u32, y as u32)
+        }
+    };
+
+    let corners = [
+        (pixel_at(left, top), (1.0 - fx) * (1.0 - fy)),
+        (pixel_at(left + 1.0, top), fx * (1.0 - fy)),
+        (pixel_at(left, top + 1.0), (1.0 - fx) * fy),
+        (pixel_at(left + 1.0, top + 1.0), fx * fy),
+    ];
+
+    let mut result = [0.0; 4];
+
+    for (pixel, weight) in corners {
+        for (channel, value) in result.iter_mut().zip(pixel.0) {
+            *channel = f64::from(value).mul_add(weight, *channel);
+        }
+    }
+
+    Rgba(result.map(|channel| channel.round().clamp(0.0, 255.0) as u8))
+}
+
+/// Shrinks the image to `width`x`height` by averaging all source pixels that
+/// fall into each target pixel. The target must not be larger than the source.
+///
+/// Colors are weighted by their alpha, so transparent pixels do not darken
+/// their neighbours.
+#[must_use]
+pub fn downscale_area(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let (source_width, source_height) = img.dimensions();
+    let bounds = |index: u32, target: u32, source: u32| {
+        let start = u64::from(index) * u64::from(source) / u64::from(target);
+        let end = (u64::from(index) + 1) * u64::from(source) / u64::from(target);
+        (start as u32, (end as u32).max(start as u32 + 1))
+    };
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (left, right) = bounds(x, width, source_width);
+        let (top, bottom) = bounds(y, height, source_height);
+
+        let mut sums = [0u64; 3];
+        let mut alpha = 0u64;
+
+        for source_y in top..bottom {
+            for source_x in left..right {
+                let Rgba([r, g, b, a]) = *img.get_pixel(source_x, source_y);
+
+                for (sum, value) in sums.iter_mut().zip([r, g, b]) {
+                    *sum += u64::from(value) * u64::from(a);
+                }
+                alpha += u64::from(a);
+            }
+        }
+
+        let count = u64::from(right - left) * u64::from(bottom - top);
+        let [r, g, b] = sums.map(|sum| (sum + alpha / 2).checked_div(alpha).unwrap_or(0) as u8);
+
+        Rgba([r, g, b, ((alpha + count / 2) / count) as u8])
+    })
+}
+
+/// Scales the image to cover `width`x`height`, keeping the aspect ratio, and
+/// crops what sticks out.
+///
+/// `focus` is the point of the image, relative to its size, that is kept as
+/// close to the center as possible.
+#[must_use]
+pub fn cover(img: &RgbaImage, width: u32, height: u32, focus: (f64, f64)) -> RgbaImage {
+    let (source_width, source_height) = (f64::from(img.width()), f64::from(img.height()));
+    let scale = (f64::from(width) / source_width).max(f64::from(height) / source_height);
+
+    // Crops the source first, so that only the visible part is resized
+    let crop_width = (f64::from(width) / scale).min(source_width);
+    let crop_height = (f64::from(height) / scale).min(source_height);
+    let left = focus
+        .0
+        .mul_add(source_width, -crop_width / 2.0)
+        .clamp(0.0, source_width - crop_width);
+    let top = focus
+        .1
+        .mul_add(source_height, -crop_height / 2.0)
+        .clamp(0.0, source_height - crop_height);
+
+    let cropped = crop_imm(
+        img,
+        left.round() as u32,
+        top.round() as u32,
+        (crop_width.round() as u32).max(1),
+        (crop_height.round() as u32).max(1),
+    );
+
+    resize(&*cropped, width, height, FilterType::Lanczos3)
+}
+
+/// Scales the image to fit within `width`x`height`, keeping the aspect ratio,
+/// and centers it on a blurred copy of itself that covers the rest.
+#[must_use]
+pub fn contain_blurred(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    // Blurring a small copy and scaling it up looks the same and is much faster
+    let small = cover(img, (width / 8).max(1), (height / 8).max(1), (0.5, 0.5));
+    let mut target = resize(&blur(&small, 2.0), width, height, FilterType::Triangle);
+
+    let (source_width, source_height) = (f64::from(img.width()), f64::from(img.height()));
+    let scale = (f64::from(width) / source_width).min(f64::from(height) / source_height);
+    let fitted = resize(
+        img,
+        ((source_width * scale).round() as u32).clamp(1, width),
+        ((source_height * scale).round() as u32).clamp(1, height),
+        FilterType::Lanczos3,
+    );
+
+    overlay(
+        &mut target,
+        &fitted,
+        i64::from((width - fitted.width()) / 2),
+        i64::from((height - fitted.height()) / 2),
+    );
+
+    target
+}
@@ -0,0 +1,50 @@
+use image::{GrayImage, Luma, Pixel, Rgba, RgbaImage};
+
+/// Grows the covered parts of `mask` by `radius` pixels in every direction,
+/// keeping the edges round.
+#[must_use]
+pub fn dilate(mask: &GrayImage, radius: u32) -> GrayImage {
+    let radius = i64::from(radius);
+    let offsets: Vec<(i64, i64)> = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+        .filter(|(dx, dy)| dx.pow(2) + dy.pow(2) <= radius.pow(2) + radius)
+        .collect();
+
+    let (width, height) = (i64::from(mask.width()), i64::from(mask.height()));
+
+    GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let value = offsets
+            .iter()
+            .map(|(dx, dy)| (i64::from(x) + dx, i64::from(y) + dy))
+            .filter(|&(x, y)| x >= 0 && y >= 0 && x < width && y < height)
+            .map(|(x, y)| mask.get_pixel(x as u32, y as u32)[0])
+            .max()
+            .unwrap_or_default();
+
+        Luma([value])
+    })
+}
+
+/// Blends `color` onto the image with `mask` placed at `(x, y)` as its
+/// coverage. Parts of the mask outside of the image are skipped.
+pub fn fill_mask(img: &mut RgbaImage, mask: &GrayImage, x: i64, y: i64, color: Rgba<u8>) {
+    for (mask_x, mask_y, coverage) in mask.enumerate_pixels() {
+        let (target_x, target_y) = (x + i64::from(mask_x), y + i64::from(mask_y));
+
+        if coverage[0] == 0
+            || target_x < 0
+            || target_y < 0
+            || target_x >= i64::from(img.width())
+            || target_y >= i64::from(img.height())
+        {
+            continue;
+        }
+
+        let alpha = u16::from(color[3]) * u16::from(coverage[0]) / 255;
+        let mut color = color;
+        color[3] = alpha as u8;
+
+        img.get_pixel_mut(target_x as u32, target_y as u32)
+            .blend(&color);
+    }
+}
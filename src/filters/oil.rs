@@ -0,0 +1,157 @@
+use std::thread;
+
+use image::{Rgba, RgbaImage};
+
+use crate::pool::available_cpus;
+
+/// Applies an oil painting effect. Every pixel takes the average color of the
+/// most common intensity level among its neighbours within `radius`.
+///
+/// Intensities are counted in a histogram of `levels + 1` bins per row, which
+/// slides along the row by removing the column leaving the window and adding
+/// the one entering it. Rows are painted in parallel on the CPUs that no
+/// other render is busy on.
+#[must_use]
+pub fn oil(img: &RgbaImage, radius: u32, levels: u32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let mut target = RgbaImage::new(width, height);
+
+    if width == 0 || height == 0 {
+        return target;
+    }
+
+    let intensities: Vec<u8> = img
+        .pixels()
+        .map(|pixel| {
+            let avg =
+                f64::from(u32::from(pixel[0]) + u32::from(pixel[1]) + u32::from(pixel[2])) / 3.0;
+            ((avg * f64::from(levels)) / 255.0).round() as u8
+        })
+        .collect();
+
+    let row_len = width as usize * 4;
+    let threads = available_cpus().min(height as usize);
+    let rows_per_thread = (height as usize).div_ceil(threads);
+
+    let paint_chunk = |chunk_idx: usize, chunk: &mut [u8]| {
+        let first_row = chunk_idx * rows_per_thread;
+
+        for (row_offset, row) in chunk.chunks_mut(row_len).enumerate() {
+            paint_row(
+                img,
+                &intensities,
+                first_row + row_offset,
+                radius as usize,
+                levels as usize + 1,
+                row,
+            );
+        }
+    };
+
+    if threads == 1 {
+        paint_chunk(0, &mut target);
+    } else {
+        thread::scope(|scope| {
+            for (chunk_idx, chunk) in target.chunks_mut(rows_per_thread * row_len).enumerate() {
+                scope.spawn(move || paint_chunk(chunk_idx, chunk));
+            }
+        });
+    }
+
+    target
+}
+
+/// Intensity histogram of the window around the current pixel.
+struct Histogram {
+    counts: Vec<u32>,
+    sums: Vec<[u32; 3]>,
+}
+
+impl Histogram {
+    /// Adds or removes all pixels of column `x` between rows `y_start` and
+    /// `y_end` inclusive.
+    fn update_column(
+        &mut self,
+        img: &RgbaImage,
+        intensities: &[u8],
+        x: usize,
+        y_start: usize,
+        y_end: usize,
+        add: bool,
+    ) {
+        let width = img.width() as usize;
+
+        for y in y_start..=y_end {
+            let idx = y * width + x;
+            let bin = usize::from(intensities[idx]);
+            let Rgba([r, g, b, _]) = *img.get_pixel(x as u32, y as u32);
+            let sum = &mut self.sums[bin];
+
+            if add {
+                self.counts[bin] += 1;
+                sum[0] += u32::from(r);
+                sum[1] += u32::from(g);
+                sum[2] += u32::from(b);
+            } else {
+                self.counts[bin] -= 1;
+                sum[0] -= u32::from(r);
+                sum[1] -= u32::from(g);
+                sum[2] -= u32::from(b);
+            }
+        }
+    }
+
+    /// Average color of the most common intensity.
+    fn dominant_color(&self) -> [u8; 3] {
+        let (bin, count) = self
+            .counts
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, count)| **count)
+            .map(|(bin, count)| (bin, (*count).max(1)))
+            .unwrap_or_default();
+        let sum = self.sums[bin];
+
+        [
+            (sum[0] / count) as u8,
+            (sum[1] / count) as u8,
+            (sum[2] / count) as u8,
+        ]
+    }
+}
+
+fn paint_row(
+    img: &RgbaImage,
+    intensities: &[u8],
+    y: usize,
+    radius: usize,
+    bins: usize,
+    out: &mut [u8],
+) {
+    let width = img.width() as usize;
+    let height = img.height() as usize;
+    let y_start = y.saturating_sub(radius);
+    let y_end = (y + radius).min(height - 1);
+
+    let mut histogram = Histogram {
+        counts: vec![0; bins],
+        sums: vec![[0; 3]; bins],
+    };
+
+    for x in 0..=radius.min(width - 1) {
+        histogram.update_column(img, intensities, x, y_start, y_end, true);
+    }
+
+    for (x, pixel) in out.chunks_exact_mut(4).enumerate() {
+        if x > radius {
+            histogram.update_column(img, intensities, x - radius - 1, y_start, y_end, false);
+        }
+
+        if x > 0 && x + radius < width {
+            histogram.update_column(img, intensities, x + radius, y_start, y_end, true);
+        }
+
+        let [r, g, b] = histogram.dominant_color();
+        pixel.copy_from_slice(&[r, g, b, 255]);
+    }
+}
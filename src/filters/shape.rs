@@ -0,0 +1,60 @@
+use image::{Pixel, Rgba, RgbaImage};
+
+/// How much of the pixel at `(x, y)` a `width`x`height` rectangle at the
+/// origin with fully rounded ends covers, anti-aliased over one pixel.
+fn pill_coverage(x: f64, y: f64, width: f64, height: f64) -> f64 {
+    let radius = width.min(height) / 2.0;
+    let (half_width, half_height) = (width / 2.0, height / 2.0);
+
+    // Signed distance to the edge, negative inside
+    let dx = (x - half_width).abs() - (half_width - radius);
+    let dy = (y - half_height).abs() - (half_height - radius);
+    let distance = dx.max(0.0).hypot(dy.max(0.0)) + dx.max(dy).min(0.0) - radius;
+
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+/// Blends a `width`x`height` rectangle with fully rounded ends onto the
+/// image at `(x, y)`, cut off after `fill` pixels from its left.
+///
+/// `color` gets the horizontal position in the rectangle, from 0 to 1, which
+/// allows for gradients.
+pub fn fill_pill(
+    img: &mut RgbaImage,
+    x: i64,
+    y: i64,
+    width: u32,
+    height: u32,
+    fill: f64,
+    color: impl Fn(f64) -> Rgba<u8>,
+) {
+    let (width_f, height_f) = (f64::from(width), f64::from(height));
+
+    for offset_y in 0..height {
+        for offset_x in 0..width {
+            let (target_x, target_y) = (x + i64::from(offset_x), y + i64::from(offset_y));
+
+            if target_x < 0
+                || target_y < 0
+                || target_x >= i64::from(img.width())
+                || target_y >= i64::from(img.height())
+            {
+                continue;
+            }
+
+            let (center_x, center_y) = (f64::from(offset_x) + 0.5, f64::from(offset_y) + 0.5);
+            let coverage = pill_coverage(center_x, center_y, width_f, height_f)
+                * (fill - f64::from(offset_x)).clamp(0.0, 1.0);
+
+            if coverage <= 0.0 {
+                continue;
+            }
+
+            let mut color = color(center_x / width_f);
+            color[3] = (f64::from(color[3]) * coverage).round() as u8;
+
+            img.get_pixel_mut(target_x as u32, target_y as u32)
+                .blend(&color);
+        }
+    }
+}
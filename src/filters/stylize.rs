@@ -0,0 +1,235 @@
+use image::{
+    codecs::jpeg::JpegEncoder, load_from_memory_with_format, DynamicImage, ImageError, ImageFormat,
+    Rgba, RgbaImage,
+};
+
+use super::color::saturate;
+
+/// The 16 colors of the CGA/EGA default palette.
+pub const RETRO: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xaa],
+    [0x00, 0xaa, 0x00],
+    [0x00, 0xaa, 0xaa],
+    [0xaa, 0x00, 0x00],
+    [0xaa, 0x00, 0xaa],
+    [0xaa, 0x55, 0x00],
+    [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xff],
+    [0x55, 0xff, 0x55],
+    [0x55, 0xff, 0xff],
+    [0xff, 0x55, 0x55],
+    [0xff, 0x55, 0xff],
+    [0xff, 0xff, 0x55],
+    [0xff, 0xff, 0xff],
+];
+
+/// Four shades of green, like the original Game Boy.
+pub const HANDHELD: [[u8; 3]; 4] = [
+    [0x0f, 0x38, 0x0f],
+    [0x30, 0x62, 0x30],
+    [0x8b, 0xac, 0x0f],
+    [0x9b, 0xbc, 0x0f],
+];
+
+pub const MONOCHROME: [[u8; 3]; 2] = [[0x00, 0x00, 0x00], [0xff, 0xff, 0xff]];
+
+/// 8x8 Bayer threshold matrix for ordered dithering.
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// A small deterministic random number generator (`SplitMix64`), so that
+/// effects look the same every time for a given seed.
+struct Rng(u64);
+
+impl Rng {
+    const fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: u32) -> u32 {
+        (self.next() % u64::from(n.max(1))) as u32
+    }
+}
+
+/// Reduces every color channel to `levels` evenly spaced values.
+pub fn posterize(img: &mut RgbaImage, levels: u8) {
+    let step = 255.0 / f32::from(levels.max(2) - 1);
+
+    for pixel in img.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = ((f32::from(*channel) / step).round() * step).round() as u8;
+        }
+    }
+}
+
+fn nearest(palette: &[[u8; 3]], color: [f32; 3]) -> [u8; 3] {
+    palette
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            let distance = |entry: &[u8; 3]| -> f32 {
+                entry
+                    .iter()
+                    .zip(color)
+                    .map(|(&value, target)| (f32::from(value) - target).powi(2))
+                    .sum()
+            };
+
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap_or_default()
+}
+
+/// Maps every pixel to the closest palette color, spreading the rounding
+/// error onto the neighbouring pixels (Floyd-Steinberg).
+pub fn dither_diffuse(img: &mut RgbaImage, palette: &[[u8; 3]]) {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut errors = vec![[0.0f32; 3]; width * (height + 1) + 1];
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = img.get_pixel_mut(x as u32, y as u32);
+            let idx = y * width + x;
+
+            let mut color = [0.0; 3];
+            for (channel, value) in color.iter_mut().enumerate() {
+                *value = (f32::from(pixel[channel]) + errors[idx][channel]).clamp(0.0, 255.0);
+            }
+
+            let chosen = nearest(palette, color);
+
+            for channel in 0..3 {
+                let error = color[channel] - f32::from(chosen[channel]);
+                pixel[channel] = chosen[channel];
+
+                if x + 1 < width {
+                    errors[idx + 1][channel] += error * 7.0 / 16.0;
+                }
+                if x > 0 {
+                    errors[idx + width - 1][channel] += error * 3.0 / 16.0;
+                }
+                errors[idx + width][channel] += error * 5.0 / 16.0;
+                if x + 1 < width {
+                    errors[idx + width + 1][channel] += error / 16.0;
+                }
+            }
+        }
+    }
+}
+
+/// Maps every pixel to the closest palette color after nudging it by a
+/// position dependent threshold (8x8 Bayer matrix).
+pub fn dither_ordered(img: &mut RgbaImage, palette: &[[u8; 3]]) {
+    // The nudge covers roughly the distance between two palette colors
+    let colors = u32::try_from(palette.len()).unwrap_or(u32::MAX).max(2);
+    let spread = 255.0 / f64::from(colors).cbrt();
+
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let threshold = f64::from(BAYER[y as usize % 8][x as usize % 8]) / 64.0 - 0.5;
+        let offset = (threshold * spread) as f32;
+
+        let color = [0, 1, 2].map(|channel| f32::from(pixel[channel]) + offset);
+        let chosen = nearest(palette, color);
+
+        pixel.0[..3].copy_from_slice(&chosen);
+    }
+}
+
+/// Oversaturates the image, adds noise and compresses it as a low quality
+/// JPEG over and over again.
+pub fn deep_fry(
+    img: &mut RgbaImage,
+    saturation: f32,
+    noise: u8,
+    iterations: u32,
+    quality: u8,
+    seed: u64,
+) -> Result<(), ImageError> {
+    let mut rng = Rng(seed);
+
+    saturate(img, saturation);
+
+    if noise > 0 {
+        let range = u32::from(noise) * 2 + 1;
+
+        for pixel in img.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                let delta = rng.below(range) as i32 - i32::from(noise);
+                *channel = (i32::from(*channel) + delta).clamp(0, 255) as u8;
+            }
+        }
+    }
+
+    let mut rgb = DynamicImage::ImageRgba8(img.clone()).into_rgb8();
+
+    for _ in 0..iterations {
+        let mut buf = Vec::new();
+        JpegEncoder::new_with_quality(&mut buf, quality).encode_image(&rgb)?;
+        rgb = load_from_memory_with_format(&buf, ImageFormat::Jpeg)?.into_rgb8();
+    }
+
+    for (target, source) in img.pixels_mut().zip(rgb.pixels()) {
+        target.0[..3].copy_from_slice(&source.0);
+    }
+
+    Ok(())
+}
+
+/// Shifts the red and blue channels apart by `shift` pixels and displaces
+/// `slices` random horizontal bands sideways. The result only depends on the
+/// image and `seed`.
+#[must_use]
+pub fn glitch(img: &RgbaImage, shift: u32, slices: u32, seed: u64) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let mut rng = Rng(seed);
+    let mut target = img.clone();
+
+    if width == 0 || height == 0 {
+        return target;
+    }
+
+    let source_x =
+        |x: u32, offset: i64| (i64::from(x) + offset).rem_euclid(i64::from(width)) as u32;
+
+    for (x, y, pixel) in target.enumerate_pixels_mut() {
+        let Rgba([_, g, _, a]) = *pixel;
+        let r = img.get_pixel(source_x(x, i64::from(shift)), y)[0];
+        let b = img.get_pixel(source_x(x, -i64::from(shift)), y)[2];
+
+        *pixel = Rgba([r, g, b, a]);
+    }
+
+    let max_offset = (width / 8).max(1);
+    let max_band = (height / 12).max(1);
+
+    for _ in 0..slices {
+        let top = rng.below(height);
+        let band = rng.below(max_band) + 1;
+        let offset = i64::from(rng.below(max_offset * 2 + 1)) - i64::from(max_offset);
+
+        for y in top..(top + band).min(height) {
+            let row: Vec<Rgba<u8>> = (0..width).map(|x| *target.get_pixel(x, y)).collect();
+
+            for x in 0..width {
+                target.put_pixel(x, y, row[source_x(x, offset) as usize]);
+            }
+        }
+    }
+
+    target
+}
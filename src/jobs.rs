@@ -0,0 +1,247 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use hyper::{body::to_bytes, header::CONTENT_TYPE, Body, Response};
+use log::{error, info};
+use serde::Serialize;
+use simd_json::{json, OwnedValue};
+use tokio::{
+    sync::Semaphore,
+    time::{interval, sleep},
+};
+
+use crate::{
+    constants::{JOB_CONCURRENCY, JOB_QUEUE},
+    error::{Error, Result},
+    logging::{with_context, RequestContext, CONTEXT},
+};
+
+/// Suggested delay for clients that were rejected because too many jobs are
+/// pending.
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Number of times a job tries to get into the render pool before it fails.
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Clone)]
+pub enum JobState {
+    Queued,
+    Running,
+    Done(OwnedValue),
+    /// The HTTP status code and error body the route replied with.
+    Failed(u16, OwnedValue),
+}
+
+#[derive(Serialize)]
+pub struct JobStatus<'a> {
+    id: &'a str,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a OwnedValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a OwnedValue>,
+}
+
+impl JobState {
+    #[must_use]
+    pub const fn status<'a>(&'a self, id: &'a str) -> JobStatus<'a> {
+        let (status, status_code, result, error) = match self {
+            Self::Queued => ("queued", None, None, None),
+            Self::Running => ("running", None, None, None),
+            Self::Done(result) => ("done", Some(200), Some(result), None),
+            Self::Failed(status_code, error) => ("failed", Some(*status_code), None, Some(error)),
+        };
+
+        JobStatus {
+            id,
+            status,
+            status_code,
+            result,
+            error,
+        }
+    }
+}
+
+/// Renders submitted in the background. At most `JOB_CONCURRENCY` jobs run at
+/// once, the others stay queued, and at most `JOB_QUEUE` jobs may be pending.
+/// Finished jobs are kept as long as cached images.
+#[derive(Clone)]
+pub struct JobStore {
+    /// Jobs by ID, with the time they finished at
+    jobs: Arc<DashMap<String, (JobState, Option<Instant>)>>,
+    counter: Arc<AtomicU64>,
+    permits: Arc<Semaphore>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl Default for JobStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JobStore {
+    #[must_use]
+    pub fn new() -> Self {
+        let store = Self {
+            jobs: Arc::new(DashMap::new()),
+            counter: Arc::new(AtomicU64::new(1)),
+            permits: Arc::new(Semaphore::new(*JOB_CONCURRENCY)),
+            pending: Arc::new(AtomicUsize::new(0)),
+        };
+        let store_clone = store.clone();
+
+        tokio::spawn(async move {
+            store_clone.cleanup_task().await;
+        });
+
+        store
+    }
+
+    async fn cleanup_task(&self) {
+        // Reap every 5mins
+        let mut interval = interval(Duration::from_secs(60 * 5));
+        // Keep each for 15mins after it finished, like the images they point to
+        let time_until_reap = Duration::from_secs(60 * 15);
+
+        loop {
+            interval.tick().await;
+
+            let right_now = Instant::now();
+
+            self.jobs.retain(|_, (_, finished)| {
+                finished.is_none_or(|finished| finished + time_until_reap > right_now)
+            });
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<JobState> {
+        self.jobs.get(id).map(|job| job.0.clone())
+    }
+
+    fn set(&self, id: &str, state: JobState) {
+        if let Some(mut job) = self.jobs.get_mut(id) {
+            let finished = matches!(state, JobState::Done(_) | JobState::Failed(..));
+            *job = (state, finished.then(Instant::now));
+        }
+    }
+
+    /// Queues a render and returns the job ID, or [`Error::Overloaded`] if too
+    /// many jobs are pending. `render` is called again after the suggested
+    /// delay whenever the render pool is full, up to `MAX_ATTEMPTS` times.
+    pub fn submit<F, Fut>(&self, context: RequestContext, render: F) -> Result<String>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Response<Body>>> + Send,
+    {
+        self.pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                (pending < *JOB_QUEUE).then_some(pending + 1)
+            })
+            .map_err(|_| Error::Overloaded(RETRY_AFTER))?;
+
+        let id = self.counter.fetch_add(1, Ordering::SeqCst).to_string();
+        self.jobs.insert(id.clone(), (JobState::Queued, None));
+
+        let store = self.clone();
+        let job_id = id.clone();
+
+        tokio::spawn(CONTEXT.scope(RefCell::new(context), async move {
+            // The semaphore is never closed
+            let _permit = store.permits.acquire().await.unwrap();
+            store.set(&job_id, JobState::Running);
+
+            let start = Instant::now();
+
+            let mut attempts = 1;
+
+            let result = loop {
+                match render().await {
+                    Err(Error::Overloaded(retry_after)) if attempts < MAX_ATTEMPTS => {
+                        attempts += 1;
+                        store.set(&job_id, JobState::Queued);
+                        sleep(retry_after).await;
+                        store.set(&job_id, JobState::Running);
+                    }
+                    result => break result,
+                }
+            };
+
+            let state = match result {
+                Ok(response) if response.status().is_success() => {
+                    JobState::Done(response_value(response).await)
+                }
+                Ok(response) => failed(response).await,
+                Err(e) => {
+                    with_context(|context| context.error = Some(e.kind()));
+                    error!("{e:?}");
+                    failed(e.into_response()).await
+                }
+            };
+
+            info!("job {job_id} finished in {:?}", start.elapsed());
+
+            store.set(&job_id, state);
+            store.pending.fetch_sub(1, Ordering::SeqCst);
+        }));
+
+        Ok(id)
+    }
+}
+
+/// Turns an unsuccessful route response into a failed job. Error bodies are
+/// kept as they are, other bodies get a code derived from the status.
+async fn failed(response: Response<Body>) -> JobState {
+    let status = response.status();
+
+    let error = match response_value(response).await {
+        error @ OwnedValue::Object(_) => error,
+        body => {
+            let reason = status
+                .canonical_reason()
+                .unwrap_or("unknown")
+                .to_lowercase();
+
+            json!({
+                "status": "error",
+                "code": reason.replace(' ', "_"),
+                "reason": reason,
+                "detail": body,
+            })
+        }
+    };
+
+    JobState::Failed(status.as_u16(), error)
+}
+
+/// Converts the body of a route response to a value for the job status. JSON
+/// bodies are embedded as-is, anything else as a string.
+async fn response_value(response: Response<Body>) -> OwnedValue {
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/json");
+    let body = to_bytes(response.into_body())
+        .await
+        .map(|body| body.to_vec())
+        .unwrap_or_default();
+
+    if is_json {
+        if let Ok(value) = simd_json::to_owned_value(&mut body.clone()) {
+            return value;
+        }
+    }
+
+    OwnedValue::from(String::from_utf8_lossy(&body).into_owned())
+}
@@ -0,0 +1,255 @@
+use std::{collections::HashMap, env::var, ffi::OsStr, fs};
+
+use ab_glyph::FontVec;
+use serde::Deserialize;
+
+use crate::constants::{
+    CAVIAR_DREAMS_FONTS, OPEN_SANS_EMOJI_FONTS, TRAVITIA_FONTS, TRAV_MEDIUM_FONTS,
+};
+
+/// Fonts that layouts can use.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FontName {
+    #[default]
+    Travitia,
+    TravMedium,
+    CaviarDreams,
+    OpenSansEmoji,
+}
+
+impl FontName {
+    /// The font followed by its fallbacks, see [`crate::text::runs`].
+    #[must_use]
+    pub fn fonts(self) -> &'static [&'static FontVec] {
+        match self {
+            Self::Travitia => &*TRAVITIA_FONTS,
+            Self::TravMedium => &*TRAV_MEDIUM_FONTS,
+            Self::CaviarDreams => &*CAVIAR_DREAMS_FONTS,
+            Self::OpenSansEmoji => &*OPEN_SANS_EMOJI_FONTS,
+        }
+    }
+}
+
+/// Horizontal alignment of text relative to the `x` of its slot.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical alignment of text relative to the `y` of its slot.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Texts of a profile that a slot can show.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TextField {
+    CharacterName,
+    Level,
+    Marriage,
+    Race,
+    PrimaryClass,
+    SecondaryClass,
+    Money,
+    PvpWins,
+    God,
+    AdventureName,
+    AdventureTime,
+    RightHandItemName,
+    RightHandItemStat,
+    LeftHandItemName,
+    LeftHandItemStat,
+}
+
+/// Icons of a profile that a slot can show.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum IconField {
+    Race,
+    PrimaryClass,
+    SecondaryClass,
+    GuildRank,
+    RightHandItem,
+    LeftHandItem,
+}
+
+/// Where and how a text is drawn. Text wider than `max_width` is shrunk down
+/// to `min_size`, which defaults to `size`, and then cut off with an ellipsis.
+#[derive(Deserialize)]
+pub struct TextBox {
+    pub x: i32,
+    pub y: i32,
+    pub size: f32,
+    pub min_size: Option<f32>,
+    pub max_width: i32,
+    #[serde(default)]
+    pub font: FontName,
+    #[serde(default)]
+    pub align: Align,
+    #[serde(default)]
+    pub anchor: Anchor,
+}
+
+impl TextBox {
+    fn validate(&self) -> Result<(), String> {
+        if self.size.is_nan() || self.size <= 0.0 {
+            return Err(format!("size {} is not positive", self.size));
+        }
+
+        if let Some(min_size) = self.min_size {
+            if min_size.is_nan() || min_size <= 0.0 || min_size > self.size {
+                return Err(format!("min_size {min_size} is not between 0 and size"));
+            }
+        }
+
+        if self.max_width < 0 {
+            return Err(format!("max_width {} is negative", self.max_width));
+        }
+
+        Ok(())
+    }
+}
+
+/// A text on the profile card.
+#[derive(Deserialize)]
+pub struct TextSlot {
+    pub field: TextField,
+    #[serde(flatten)]
+    pub text: TextBox,
+}
+
+#[derive(Deserialize)]
+pub struct IconSlot {
+    pub field: IconField,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// Area that badges are placed in, and the number of full size badges that
+/// fit into one row.
+///
+/// `columns` optionally places a single row of full size badges at these
+/// offsets from `x` instead of spacing them evenly, as long as there are not
+/// more badges than offsets.
+#[derive(Deserialize)]
+pub struct BadgeArea {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+    pub per_row: u32,
+    #[serde(default)]
+    pub columns: Vec<u32>,
+}
+
+/// A progress bar that shows the bar of the same name in the request, with
+/// its label centered on it at font size `size`.
+#[derive(Deserialize)]
+pub struct BarSlot {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub size: f32,
+    #[serde(default)]
+    pub font: FontName,
+}
+
+/// Where everything on a profile card is drawn.
+#[derive(Deserialize)]
+pub struct Layout {
+    pub texts: Vec<TextSlot>,
+    pub icons: Vec<IconSlot>,
+    pub badges: BadgeArea,
+    #[serde(default)]
+    pub bars: Vec<BarSlot>,
+}
+
+impl Layout {
+    /// Checks the sizes in the layout, which serde alone cannot.
+    fn validate(&self) -> Result<(), String> {
+        for slot in &self.texts {
+            slot.text.validate()?;
+        }
+
+        if self.badges.per_row == 0 {
+            return Err(String::from("badges.per_row is 0"));
+        }
+
+        if let Some(column) = self
+            .badges
+            .columns
+            .iter()
+            .find(|column| **column >= self.badges.width)
+        {
+            return Err(format!(
+                "badges.columns offset {column} is outside of the width"
+            ));
+        }
+
+        for bar in &self.bars {
+            if bar.size.is_nan() || bar.size <= 0.0 {
+                return Err(format!(
+                    "size {} of bar {} is not positive",
+                    bar.size, bar.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the embedded default layout and every `*.json` file in
+/// `LAYOUT_DIR`, named after the file. A file named `default.json` replaces
+/// the embedded layout.
+///
+/// Panics on files that are not valid layouts, so that they fail at startup.
+pub fn load(default: &[u8]) -> HashMap<String, Layout> {
+    let mut layouts = HashMap::new();
+
+    let layout: Layout =
+        simd_json::from_slice(&mut default.to_vec()).expect("invalid default layout");
+    layout
+        .validate()
+        .unwrap_or_else(|e| panic!("invalid default layout: {e}"));
+    layouts.insert(String::from("default"), layout);
+
+    if let Ok(dir) = var("LAYOUT_DIR") {
+        for entry in fs::read_dir(dir).expect("could not read LAYOUT_DIR") {
+            let path = entry.expect("could not read LAYOUT_DIR").path();
+
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+
+            let name = path
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .expect("invalid layout file name")
+                .to_string();
+            let mut data = fs::read(&path).expect("could not read layout");
+            let layout: Layout = simd_json::from_slice(&mut data)
+                .unwrap_or_else(|e| panic!("invalid layout {}: {e}", path.display()));
+            layout
+                .validate()
+                .unwrap_or_else(|e| panic!("invalid layout {}: {e}", path.display()));
+
+            layouts.insert(name, layout);
+        }
+    }
+
+    layouts
+}
@@ -0,0 +1,126 @@
+use std::{
+    cell::RefCell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use env_logger::{Builder, Env};
+use hyper::HeaderMap;
+use serde::Serialize;
+
+/// Fields attached to every log line emitted while handling a request.
+#[derive(Default, Serialize)]
+pub struct RequestContext {
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+tokio::task_local! {
+    pub static CONTEXT: RefCell<RequestContext>;
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: f64,
+    level: &'static str,
+    target: &'a str,
+    msg: String,
+    #[serde(flatten)]
+    context: Option<&'a RequestContext>,
+}
+
+/// Sets up JSON logging. `RUST_LOG` is respected and defaults to `info`.
+pub fn init() {
+    Builder::from_env(Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+
+            let write_line = |context: Option<&RequestContext>| {
+                let line = Line {
+                    ts,
+                    level: record.level().as_str(),
+                    target: record.target(),
+                    msg: record.args().to_string(),
+                    context,
+                };
+
+                simd_json::to_string(&line).map_err(std::io::Error::other)
+            };
+
+            let line = CONTEXT
+                .try_with(|context| {
+                    context
+                        .try_borrow()
+                        .map_or_else(|_| write_line(None), |context| write_line(Some(&context)))
+                })
+                .unwrap_or_else(|_| write_line(None))?;
+
+            writeln!(buf, "{line}")
+        })
+        .init();
+}
+
+/// Updates the context of the current request, if there is one.
+pub fn with_context(f: impl FnOnce(&mut RequestContext)) {
+    let _ = CONTEXT.try_with(|context| f(&mut context.borrow_mut()));
+}
+
+/// Returns the ID of the request being handled, if there is one.
+#[must_use]
+pub fn current_request_id() -> Option<String> {
+    CONTEXT
+        .try_with(|context| context.borrow().request_id.clone())
+        .ok()
+}
+
+#[must_use]
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Takes the request ID from the `X-Request-Id` header if it is sane,
+/// otherwise generates a new one.
+#[must_use]
+pub fn request_id(headers: &HeaderMap) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    if let Some(request_id) = headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty()
+                && value.len() <= 64
+                && value
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        })
+    {
+        return request_id.to_string();
+    }
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+
+    format!("{:016x}", hasher.finish())
+}
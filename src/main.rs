@@ -8,36 +8,63 @@
     clippy::missing_errors_doc,
     clippy::module_name_repetitions
 )]
-use std::{convert::Infallible, env::set_var, net::SocketAddr, sync::Arc, time::Instant};
+use std::{
+    cell::RefCell, convert::Infallible, io::Read, net::SocketAddr, sync::Arc, time::Instant,
+};
 
 use bytes::Buf;
 use hyper::{
+    header::HeaderValue,
+    server::conn::AddrStream,
     service::{make_service_fn, service_fn},
     Body, Error, Method, Request, Response, Server,
 };
-use libc::{c_int, sighandler_t, signal, SIGINT, SIGTERM};
-use log::{error, info};
+use log::{error, info, warn};
 use serde::Deserialize;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+    time::timeout,
+};
 
 use crate::{
     cache::ImageCache,
-    constants::PORT,
+    constants::{PORT, SHUTDOWN_TIMEOUT},
+    jobs::JobStore,
+    logging::{millis, with_context, RequestContext, CONTEXT},
+    metrics::METRICS,
+    pool::RenderPool,
+    ratelimit::Ratelimiter,
     routes::{
         adventures::genadventures,
         chess::genchess,
-        imageops::{edges_endpoint, invert_endpoint, oil_endpoint, pixelate},
+        health::{healthz, readyz},
+        imageops::{imageops, OPERATIONS},
         index::index,
+        jobs::{job_status, job_submitted, JobJson},
+        metrics::metrics,
         overlay::genoverlay,
         profile::genprofile,
     },
 };
 
+pub mod auth;
 pub mod cache;
 pub mod constants;
+pub mod decoder;
+pub mod emoji;
 pub mod encoder;
 pub mod error;
 pub mod fetcher;
+pub mod filters;
+pub mod jobs;
+pub mod layout;
+pub mod logging;
+pub mod metrics;
+pub mod pool;
+pub mod ratelimit;
 pub mod routes;
+pub mod text;
 
 #[derive(Deserialize)]
 struct GetImage {
@@ -48,9 +75,51 @@ async fn handle(
     request: Request<Body>,
     fetcher: Arc<fetcher::Fetcher>,
     images: ImageCache,
+    pool: RenderPool,
+    jobs: JobStore,
+    ratelimiter: Ratelimiter,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Error> {
+    let request_id = logging::request_id(request.headers());
+    let context = RequestContext {
+        request_id: request_id.clone(),
+        ..RequestContext::default()
+    };
+
+    let mut resp = CONTEXT
+        .scope(
+            RefCell::new(context),
+            respond(
+                request,
+                fetcher,
+                images,
+                pool,
+                jobs,
+                ratelimiter,
+                remote_addr,
+            ),
+        )
+        .await?;
+
+    // SAFETY: Request IDs are either generated or validated to be ASCII
+    resp.headers_mut()
+        .insert("x-request-id", HeaderValue::from_str(&request_id).unwrap());
+
+    Ok(resp)
+}
+
+async fn respond(
+    request: Request<Body>,
+    fetcher: Arc<fetcher::Fetcher>,
+    images: ImageCache,
+    pool: RenderPool,
+    jobs: JobStore,
+    ratelimiter: Ratelimiter,
+    remote_addr: SocketAddr,
 ) -> Result<Response<Body>, Error> {
     let start = Instant::now();
     let images = &images;
+    let pool = &pool;
 
     let (parts, body) = request.into_parts();
 
@@ -61,43 +130,68 @@ async fn handle(
     let query = parts.uri.query();
     let method = parts.method;
 
-    if method == Method::POST {
-        if let Some(server_auth) = constants::AUTH_KEY.as_ref() {
-            if let Some(client_auth) = parts.headers.get("authorization") {
-                if client_auth != server_auth {
-                    return Ok(Response::builder().status(403).body(Body::empty()).unwrap());
-                }
-            } else {
-                return Ok(Response::builder().status(403).body(Body::empty()).unwrap());
+    with_context(|context| {
+        context.method = Some(method.to_string());
+        context.route = Some(path.to_string());
+    });
+
+    let response: error::Result<Response<Body>> = async {
+        let mut key_name = None;
+        let client_ip = ratelimit::client_ip(remote_addr.ip(), &parts.headers);
+
+        if method == Method::POST {
+            key_name = auth::authenticate(&parts.headers)?;
+            with_context(|context| context.key = key_name);
+
+            // Jobs are charged to the route they render instead
+            if path != "/api/jobs" {
+                ratelimiter.check(path, key_name, client_ip)?;
             }
         }
-    }
 
-    let response: error::Result<Response<Body>> = async {
         match (&method, path) {
-            (&Method::POST, "/api/genadventures") => {
-                genadventures(&simd_json::from_reader(reader)?, images)
-            }
-            (&Method::POST, "/api/genchess") => genchess(&simd_json::from_reader(reader)?, images),
-            (&Method::POST, "/api/imageops/pixel") => {
-                pixelate(simd_json::from_reader(reader)?, fetcher, images).await
-            }
-            (&Method::POST, "/api/imageops/invert") => {
-                invert_endpoint(simd_json::from_reader(reader)?, fetcher, images).await
-            }
-            (&Method::POST, "/api/imageops/edges") => {
-                edges_endpoint(simd_json::from_reader(reader)?, fetcher, images).await
-            }
-            (&Method::POST, "/api/imageops/oil") => {
-                oil_endpoint(simd_json::from_reader(reader)?, fetcher, images).await
-            }
-            (&Method::POST, "/api/genoverlay") => {
-                genoverlay(simd_json::from_reader(reader)?, fetcher, images).await
+            (&Method::POST, "/api/jobs") => {
+                let job: JobJson = simd_json::from_reader(reader)?;
+
+                if !is_render_route(&job.route) {
+                    return Ok(Response::builder().status(404).body(Body::empty()).unwrap());
+                }
+
+                // Jobs count towards the limits of the route they render only
+                ratelimiter.check(&job.route, key_name, client_ip)?;
+
+                let context = RequestContext {
+                    request_id: logging::current_request_id().unwrap_or_default(),
+                    method: Some(method.to_string()),
+                    route: Some(job.route.clone()),
+                    key: key_name,
+                    ..RequestContext::default()
+                };
+                let route = job.route;
+                let body = simd_json::to_vec(&job.body)?;
+                let images = images.clone();
+                let pool = pool.clone();
+
+                let id = jobs.submit(context, move || {
+                    let route = route.clone();
+                    let body = body.clone();
+                    let fetcher = fetcher.clone();
+                    let images = images.clone();
+                    let pool = pool.clone();
+
+                    async move { render(&route, body.as_slice(), fetcher, &pool, &images).await }
+                })?;
+
+                job_submitted(&id, &jobs)
             }
-            (&Method::POST, "/api/genprofile") => {
-                genprofile(simd_json::from_reader(reader)?, fetcher, images).await
+            (&Method::POST, _) => render(path, reader, fetcher, pool, images).await,
+            (&Method::GET, _) if path.starts_with("/api/jobs/") => {
+                job_status(&path["/api/jobs/".len()..], &jobs)
             }
             (&Method::GET, "/") => index(),
+            (&Method::GET, "/healthz") => healthz(),
+            (&Method::GET, "/readyz") => readyz(images, pool),
+            (&Method::GET, "/metrics") => metrics(images, pool),
             (&Method::GET, "/image") => query.map_or_else(
                 || Ok(Response::builder().status(400).body(Body::empty()).unwrap()),
                 |query| match serde_urlencoded::from_str::<GetImage>(query) {
@@ -122,6 +216,7 @@ async fn handle(
     let resp = match response {
         Ok(r) => r,
         Err(e) => {
+            with_context(|context| context.error = Some(e.kind()));
             error!("{:?}", e);
             e.into_response()
         }
@@ -129,49 +224,142 @@ async fn handle(
 
     let end = Instant::now();
 
+    METRICS.observe_request(path, resp.status().as_u16(), end - start);
+
+    with_context(|context| {
+        context.status = Some(resp.status().as_u16());
+        context.duration_ms = Some(millis(end - start));
+    });
+
     info!("{} {} {} {:?}", method, path, resp.status(), end - start);
 
     Ok(resp)
 }
 
-pub extern "C" fn handler(_: c_int) {
-    std::process::exit(0);
+/// Routes that render images, which can also be submitted as jobs.
+const RENDER_ROUTES: &[&str] = &[
+    "/api/genadventures",
+    "/api/genchess",
+    "/api/genoverlay",
+    "/api/genprofile",
+    "/api/imageops/pipeline",
+];
+
+fn is_render_route(path: &str) -> bool {
+    RENDER_ROUTES.contains(&path)
+        || path
+            .strip_prefix("/api/imageops/")
+            .is_some_and(|operation| OPERATIONS.contains(&operation))
 }
 
-unsafe fn set_os_handlers() {
-    signal(SIGINT, handler as extern "C" fn(_) as sighandler_t);
-    signal(SIGTERM, handler as extern "C" fn(_) as sighandler_t);
+/// Renders the image for a `POST` route with the given JSON body.
+async fn render(
+    path: &str,
+    reader: impl Read,
+    fetcher: Arc<fetcher::Fetcher>,
+    pool: &RenderPool,
+    images: &ImageCache,
+) -> error::Result<Response<Body>> {
+    match path {
+        "/api/genadventures" => genadventures(simd_json::from_reader(reader)?, pool, images).await,
+        "/api/genchess" => genchess(simd_json::from_reader(reader)?, pool, images).await,
+        "/api/genoverlay" => {
+            genoverlay(simd_json::from_reader(reader)?, fetcher, pool, images).await
+        }
+        "/api/genprofile" => {
+            genprofile(simd_json::from_reader(reader)?, fetcher, pool, images).await
+        }
+        _ => match path.strip_prefix("/api/imageops/") {
+            Some(operation) => imageops(operation, reader, fetcher, pool, images).await,
+            None => Ok(Response::builder().status(404).body(Body::empty()).unwrap()),
+        },
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    unsafe { set_os_handlers() };
+/// Resolves once SIGINT or SIGTERM is received.
+async fn shutdown_signal() {
+    let mut sigint = signal(SignalKind::interrupt()).expect("could not install SIGINT handler");
+    let mut sigterm = signal(SignalKind::terminate()).expect("could not install SIGTERM handler");
 
-    set_var("RUST_LOG", "info");
+    tokio::select! {
+        _ = sigint.recv() => {},
+        _ = sigterm.recv() => {},
+    }
+}
 
-    env_logger::init();
+#[tokio::main]
+async fn main() {
+    logging::init();
 
     let listen_address = SocketAddr::from(([0, 0, 0, 0], *PORT));
 
     info!("okapi starting on {}", listen_address);
 
+    // Fails early on broken layout files instead of on the first profile.
+    // Emoji are loaded with the other assets, after the server is up
+    lazy_static::initialize(&constants::LAYOUTS);
+
+    tokio::task::spawn_blocking(constants::load_assets);
+
     let client = Arc::new(fetcher::Fetcher::new());
     let images = ImageCache::new();
+    let pool = RenderPool::new();
+    let jobs = JobStore::new();
+    let ratelimiter = Ratelimiter::new();
 
-    let make_service = make_service_fn(|_conn| {
+    let make_service = make_service_fn(|conn: &AddrStream| {
         let client = client.clone();
         let images = images.clone();
+        let pool = pool.clone();
+        let jobs = jobs.clone();
+        let ratelimiter = ratelimiter.clone();
+        let remote_addr = conn.remote_addr();
 
-        async {
+        async move {
             Ok::<_, Infallible>(service_fn(move |req| {
-                handle(req, client.clone(), images.clone())
+                handle(
+                    req,
+                    client.clone(),
+                    images.clone(),
+                    pool.clone(),
+                    jobs.clone(),
+                    ratelimiter.clone(),
+                    remote_addr,
+                )
             }))
         }
     });
 
-    let server = Server::bind(&listen_address).serve(make_service);
+    let shutdown = Arc::new(Notify::new());
+    let shutdown_clone = shutdown.clone();
+
+    let server = Server::bind(&listen_address)
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown_clone.notified().await });
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => {
+            if let Err(e) = result {
+                error!("Server error: {e}");
+            }
+
+            return;
+        }
+        () = shutdown_signal() => {}
+    }
+
+    info!(
+        "Shutting down, waiting up to {:?} for in-flight requests",
+        *SHUTDOWN_TIMEOUT
+    );
+
+    // Stops accepting new connections and waits for in-flight requests
+    shutdown.notify_one();
 
-    if let Err(e) = server.await {
-        error!("Server error: {}", e);
+    match timeout(*SHUTDOWN_TIMEOUT, server).await {
+        Ok(Ok(())) => info!("All in-flight requests finished"),
+        Ok(Err(e)) => error!("Server error: {e}"),
+        Err(_) => warn!("Shutdown deadline exceeded, aborting in-flight requests"),
     }
 }
@@ -0,0 +1,229 @@
+use std::{
+    fmt::{self, Display, Write},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+use crate::{cache::ImageCache, pool::RenderPool, routes::imageops::OPERATIONS};
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+/// Routes besides the image operations that get their own label, everything
+/// else is counted as `other` to keep the number of series bounded.
+const KNOWN_ROUTES: &[&str] = &[
+    "/",
+    "/image",
+    "/healthz",
+    "/readyz",
+    "/metrics",
+    "/api/genadventures",
+    "/api/genchess",
+    "/api/genoverlay",
+    "/api/genprofile",
+    "/api/jobs",
+];
+
+/// Upper bounds of the latency histogram buckets in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, upper_bound) in self.buckets.iter().zip(BUCKETS) {
+            if seconds <= upper_bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        for (bucket, upper_bound) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"{upper_bound}\"}} {}",
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)).as_secs_f64();
+
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {sum}");
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+/// Label of the route a request was made to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Route {
+    Path(&'static str),
+    /// An image operation or the pipeline.
+    ImageOp(&'static str),
+}
+
+impl Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => f.write_str(path),
+            Self::ImageOp(operation) => write!(f, "/api/imageops/{operation}"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    requests: DashMap<(Route, u16), Histogram>,
+    upstream: DashMap<(String, String), Histogram>,
+    stages: DashMap<(&'static str, &'static str), Histogram>,
+}
+
+impl Metrics {
+    /// Records a handled request. Unknown paths are recorded as `other`.
+    pub fn observe_request(&self, path: &str, status: u16, duration: Duration) {
+        let route = if path.starts_with("/api/jobs/") {
+            Route::Path("/api/jobs/{id}")
+        } else if let Some(operation) = path.strip_prefix("/api/imageops/") {
+            OPERATIONS
+                .iter()
+                .chain(&["pipeline"])
+                .find(|name| **name == operation)
+                .map_or(Route::Path("other"), |name| Route::ImageOp(name))
+        } else {
+            KNOWN_ROUTES
+                .iter()
+                .find(|route| **route == path)
+                .map_or(Route::Path("other"), |route| Route::Path(route))
+        };
+
+        self.requests
+            .entry((route, status))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Records a request to an upstream image host. `status` is `error` if no
+    /// response was received.
+    pub fn observe_upstream(&self, host: &str, status: &str, duration: Duration) {
+        self.upstream
+            .entry((host.to_string(), status.to_string()))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Records the runtime of a render stage (`decode`, `composite` or
+    /// `encode`) of a route.
+    pub fn observe_stage(&self, route: &'static str, stage: &'static str, duration: Duration) {
+        self.stages
+            .entry((route, stage))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Runs `f` and records its runtime as the given render stage of a route.
+    pub fn time_stage<T>(
+        &self,
+        route: &'static str,
+        stage: &'static str,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.observe_stage(route, stage, start.elapsed());
+
+        result
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    #[must_use]
+    pub fn render(&self, images: &ImageCache, pool: &RenderPool) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP okapi_requests_total Number of handled requests.\n");
+        out.push_str("# TYPE okapi_requests_total counter\n");
+        for entry in &self.requests {
+            let (route, status) = entry.key();
+            let _ = writeln!(
+                out,
+                "okapi_requests_total{{route=\"{route}\",status=\"{status}\"}} {}",
+                entry.value().count.load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str("# HELP okapi_request_duration_seconds Time taken to handle requests.\n");
+        out.push_str("# TYPE okapi_request_duration_seconds histogram\n");
+        for entry in &self.requests {
+            let (route, status) = entry.key();
+            entry.value().write(
+                &mut out,
+                "okapi_request_duration_seconds",
+                &format!("route=\"{route}\",status=\"{status}\""),
+            );
+        }
+
+        out.push_str(
+            "# HELP okapi_upstream_request_duration_seconds Time taken to download images.\n",
+        );
+        out.push_str("# TYPE okapi_upstream_request_duration_seconds histogram\n");
+        for entry in &self.upstream {
+            let (host, status) = entry.key();
+            entry.value().write(
+                &mut out,
+                "okapi_upstream_request_duration_seconds",
+                &format!("host=\"{host}\",status=\"{status}\""),
+            );
+        }
+
+        out.push_str("# HELP okapi_render_stage_duration_seconds Time taken by render stages.\n");
+        out.push_str("# TYPE okapi_render_stage_duration_seconds histogram\n");
+        for entry in &self.stages {
+            let (route, stage) = entry.key();
+            entry.value().write(
+                &mut out,
+                "okapi_render_stage_duration_seconds",
+                &format!("route=\"{route}\",stage=\"{stage}\""),
+            );
+        }
+
+        let (entries, bytes) = images.stats();
+
+        out.push_str("# HELP okapi_image_cache_entries Number of images in the cache.\n");
+        out.push_str("# TYPE okapi_image_cache_entries gauge\n");
+        let _ = writeln!(out, "okapi_image_cache_entries {entries}");
+
+        out.push_str("# HELP okapi_image_cache_bytes Size of all images in the cache.\n");
+        out.push_str("# TYPE okapi_image_cache_bytes gauge\n");
+        let _ = writeln!(out, "okapi_image_cache_bytes {bytes}");
+
+        out.push_str(
+            "# HELP okapi_render_jobs_queued Number of render jobs waiting for a thread.\n",
+        );
+        out.push_str("# TYPE okapi_render_jobs_queued gauge\n");
+        let _ = writeln!(out, "okapi_render_jobs_queued {}", pool.queued());
+
+        out.push_str("# HELP okapi_render_jobs_running Number of render jobs being rendered.\n");
+        out.push_str("# TYPE okapi_render_jobs_running gauge\n");
+        let _ = writeln!(out, "okapi_render_jobs_running {}", pool.busy());
+
+        out
+    }
+}
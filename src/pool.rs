@@ -0,0 +1,145 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use tokio::sync::oneshot;
+
+use crate::{
+    constants::{RENDER_QUEUE, RENDER_THREADS},
+    error::{Error, Result},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Suggested delay for clients that were rejected because the queue is full.
+const RETRY_AFTER: Duration = Duration::from_secs(2);
+
+/// Number of workers currently rendering. There is only one pool per
+/// process, so it is shared with jobs that split their work, see
+/// [`available_cpus`].
+static BUSY: AtomicUsize = AtomicUsize::new(0);
+
+/// A fixed number of threads for CPU-bound rendering, so that it does not
+/// block the async runtime. Jobs wait in a bounded queue; once it is full,
+/// new jobs are rejected with [`Error::Overloaded`].
+#[derive(Clone)]
+pub struct RenderPool {
+    sender: SyncSender<Job>,
+    queued: Arc<AtomicUsize>,
+    capacity: usize,
+}
+
+impl Default for RenderPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderPool {
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, receiver) = sync_channel::<Job>(*RENDER_QUEUE);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
+
+        for idx in 0..*RENDER_THREADS {
+            let receiver = receiver.clone();
+            let queued = queued.clone();
+
+            thread::Builder::new()
+                .name(format!("render-{idx}"))
+                .spawn(move || worker(&receiver, &queued))
+                .expect("could not spawn render thread");
+        }
+
+        Self {
+            sender,
+            queued,
+            capacity: *RENDER_QUEUE,
+        }
+    }
+
+    /// Runs `f` on the pool and waits for its result. If the returned future
+    /// is dropped before a worker picked up the job, for example because the
+    /// client disconnected, the job is skipped.
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        let job: Job = Box::new(move || {
+            if !tx.is_closed() {
+                let _ = tx.send(f());
+            }
+        });
+
+        self.queued.fetch_add(1, Ordering::SeqCst);
+
+        if let Err(e) = self.sender.try_send(job) {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+
+            return match e {
+                TrySendError::Full(_) => Err(Error::Overloaded(RETRY_AFTER)),
+                TrySendError::Disconnected(_) => unreachable!("render threads never exit"),
+            };
+        }
+
+        // The sender is only dropped without sending if the job is skipped,
+        // which cannot happen while we are still waiting
+        rx.await.expect("render job was dropped")
+    }
+
+    /// Number of jobs waiting for a worker.
+    #[must_use]
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Number of workers currently rendering.
+    #[must_use]
+    pub fn busy(&self) -> usize {
+        BUSY.load(Ordering::SeqCst)
+    }
+
+    /// Maximum number of jobs that can wait for a worker.
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Number of CPUs a render job may use: its own and those that no other
+/// worker is busy on.
+#[must_use]
+pub fn available_cpus() -> usize {
+    let cpus = thread::available_parallelism().map_or(1, usize::from);
+
+    // The calling worker is busy itself
+    (cpus + 1)
+        .saturating_sub(BUSY.load(Ordering::SeqCst))
+        .max(1)
+}
+
+fn worker(receiver: &Mutex<Receiver<Job>>, queued: &AtomicUsize) {
+    loop {
+        // The lock is only held while waiting for the next job
+        let job = receiver.lock().unwrap().recv();
+
+        let Ok(job) = job else {
+            return;
+        };
+
+        queued.fetch_sub(1, Ordering::SeqCst);
+        BUSY.fetch_add(1, Ordering::SeqCst);
+        job();
+        BUSY.fetch_sub(1, Ordering::SeqCst);
+    }
+}
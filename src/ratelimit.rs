@@ -0,0 +1,191 @@
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use hyper::HeaderMap;
+use tokio::time::interval;
+
+use crate::{
+    constants::{RATELIMITS, TRUSTED_PROXIES},
+    error::{Error, Result},
+};
+
+/// A single configured limit: `capacity` requests, refilled evenly over
+/// `period`.
+#[derive(Clone, Copy)]
+struct Limit {
+    capacity: f64,
+    period: Duration,
+}
+
+impl Limit {
+    fn refill_per_second(self) -> f64 {
+        self.capacity / self.period.as_secs_f64()
+    }
+}
+
+/// A rule applies to a route (or `*` for every route), optionally only for a
+/// single API key.
+struct Rule {
+    key: Option<String>,
+    route: String,
+    limit: Limit,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    period: Duration,
+}
+
+#[derive(Clone)]
+pub struct Ratelimiter {
+    rules: Arc<Vec<Rule>>,
+    /// Buckets by client and index of the rule they belong to.
+    buckets: Arc<DashMap<(String, usize), Bucket>>,
+}
+
+impl Default for Ratelimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ratelimiter {
+    #[must_use]
+    pub fn new() -> Self {
+        let rules = RATELIMITS
+            .as_deref()
+            .map(|rules| {
+                rules
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|rule| !rule.is_empty())
+                    .map(|rule| parse_rule(rule).expect("invalid RATELIMITS rule"))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let ratelimiter = Self {
+            rules: Arc::new(rules),
+            buckets: Arc::new(DashMap::new()),
+        };
+        let ratelimiter_clone = ratelimiter.clone();
+
+        tokio::spawn(async move {
+            ratelimiter_clone.cleanup_task().await;
+        });
+
+        ratelimiter
+    }
+
+    async fn cleanup_task(&self) {
+        // Idle buckets are full again after one period, so they can be dropped
+        let mut interval = interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            let right_now = Instant::now();
+
+            self.buckets
+                .retain(|_, bucket| bucket.updated + bucket.period > right_now);
+        }
+    }
+
+    /// Looks up the most specific rule for a route, preferring rules for
+    /// the given key over global ones and exact routes over `*`.
+    ///
+    /// Returns the index of the rule and its limit.
+    fn find_rule(&self, key: Option<&str>, route: &str) -> Option<(usize, Limit)> {
+        let lookups = [(key, route), (key, "*"), (None, route), (None, "*")];
+
+        lookups.into_iter().find_map(|(key, route)| {
+            self.rules
+                .iter()
+                .position(|rule| rule.key.as_deref() == key && rule.route == route)
+                .map(|index| (index, self.rules[index].limit))
+        })
+    }
+
+    /// Takes a token from the bucket of the client for the rule matching
+    /// `route`. Clients are identified by their API key name if they have one,
+    /// otherwise by their IP. Routes sharing a rule, like those matched by
+    /// `*`, share its bucket.
+    ///
+    /// Returns [`Error::TooManyRequests`] with the time until the next token
+    /// is available if the bucket is empty.
+    pub fn check(&self, route: &str, key: Option<&str>, ip: IpAddr) -> Result<()> {
+        let Some((rule, limit)) = self.find_rule(key, route) else {
+            return Ok(());
+        };
+
+        let client = key.map_or_else(|| ip.to_string(), |key| format!("key:{key}"));
+        let right_now = Instant::now();
+        let mut bucket = self.buckets.entry((client, rule)).or_insert(Bucket {
+            tokens: limit.capacity,
+            updated: right_now,
+            period: limit.period,
+        });
+
+        let elapsed = (right_now - bucket.updated).as_secs_f64();
+        let tokens = elapsed
+            .mul_add(limit.refill_per_second(), bucket.tokens)
+            .min(limit.capacity);
+        bucket.tokens = if tokens >= 1.0 { tokens - 1.0 } else { tokens };
+        bucket.updated = right_now;
+        drop(bucket);
+
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(Error::TooManyRequests(Duration::from_secs_f64(
+                (1.0 - tokens) / limit.refill_per_second(),
+            )))
+        }
+    }
+}
+
+/// Parses a rule in the format `[key@]route=capacity/seconds`.
+fn parse_rule(rule: &str) -> Option<Rule> {
+    let (target, limit) = rule.split_once('=')?;
+    let (key, route) = match target.split_once('@') {
+        Some((key, route)) => (Some(key.to_string()), route),
+        None => (None, target),
+    };
+    let (capacity, seconds) = limit.split_once('/')?;
+    let capacity = capacity.parse::<u32>().ok().filter(|c| *c > 0)?;
+    let seconds = seconds.parse::<u64>().ok().filter(|s| *s > 0)?;
+
+    Some(Rule {
+        key,
+        route: route.to_string(),
+        limit: Limit {
+            capacity: f64::from(capacity),
+            period: Duration::from_secs(seconds),
+        },
+    })
+}
+
+/// Determines the address of the client. `X-Forwarded-For` is only honored
+/// when the direct peer is a trusted proxy, in which case the right-most
+/// address not belonging to a trusted proxy is used.
+#[must_use]
+pub fn client_ip(remote: IpAddr, headers: &HeaderMap) -> IpAddr {
+    if !TRUSTED_PROXIES.contains(&remote) {
+        return remote;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .rev()
+        .find(|addr| !TRUSTED_PROXIES.contains(addr))
+        .unwrap_or(remote)
+}
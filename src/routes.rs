@@ -1,6 +1,9 @@
 pub mod adventures;
 pub mod chess;
+pub mod health;
 pub mod imageops;
 pub mod index;
+pub mod jobs;
+pub mod metrics;
 pub mod overlay;
 pub mod profile;
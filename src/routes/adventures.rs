@@ -6,9 +6,12 @@ use serde::Deserialize;
 
 use crate::{
     cache::ImageCache,
-    constants::{ADVENTURES, TRAVITIA_FONT},
+    constants::{ADVENTURES, TRAVITIA_FONTS},
     encoder::encode_png,
     error::Result,
+    metrics::METRICS,
+    pool::RenderPool,
+    text::{fit_text, runs, RunKind},
 };
 
 #[derive(Deserialize)]
@@ -18,42 +21,63 @@ pub struct AdventuresJson {
 
 const WHITE: Rgb<u8> = Rgb([0, 0, 0]);
 const SCALE: PxScale = PxScale { x: 20.0, y: 20.0 };
+const MIN_SCALE: PxScale = PxScale { x: 14.0, y: 14.0 };
+const MAX_WIDTH: i32 = 100;
 
-pub fn genadventures(body: &AdventuresJson, images: &ImageCache) -> Result<Response<Body>> {
-    let mut buffers: Vec<Vec<u8>> = Vec::with_capacity(30);
+const ROUTE: &str = "/api/genadventures";
 
-    for idx in 0..30 {
-        let current_chances = &body.percentages[idx];
-        let chance_min = &current_chances[0];
-        let chance_max = &current_chances[1];
+pub async fn genadventures(
+    body: AdventuresJson,
+    pool: &RenderPool,
+    images: &ImageCache,
+) -> Result<Response<Body>> {
+    let buffers = pool
+        .run(move || {
+            let mut buffers: Vec<Vec<u8>> = Vec::with_capacity(30);
 
-        let mut new_image = ADVENTURES[idx].clone();
+            for idx in 0..30 {
+                let current_chances = &body.percentages[idx];
+                let chance_min = &current_chances[0];
+                let chance_max = &current_chances[1];
 
-        draw_text_mut(
-            &mut new_image,
-            WHITE,
-            314,
-            148,
-            SCALE,
-            100,
-            &*TRAVITIA_FONT,
-            &format!("{chance_min}% to"),
-        );
-        draw_text_mut(
-            &mut new_image,
-            WHITE,
-            314,
-            168,
-            SCALE,
-            100,
-            &*TRAVITIA_FONT,
-            &format!("{chance_max}%"),
-        );
+                let new_image = METRICS.time_stage(ROUTE, "composite", || {
+                    let mut new_image = ADVENTURES[idx].clone();
 
-        let buf = encode_png(&new_image)?;
+                    for (y, text) in [
+                        (148, format!("{chance_min}% to")),
+                        (168, format!("{chance_max}%")),
+                    ] {
+                        let (scale, text) =
+                            fit_text(&*TRAVITIA_FONTS, SCALE, MIN_SCALE, MAX_WIDTH, &text);
 
-        buffers.push(buf);
-    }
+                        for run in runs(&*TRAVITIA_FONTS, scale, &text) {
+                            // Percentages never contain emoji
+                            if let RunKind::Text { font, text } = run.kind {
+                                draw_text_mut(
+                                    &mut new_image,
+                                    WHITE,
+                                    314 + run.x,
+                                    y + run.y,
+                                    scale,
+                                    MAX_WIDTH - run.x,
+                                    *font,
+                                    text,
+                                );
+                            }
+                        }
+                    }
+
+                    new_image
+                });
+
+                let buf = METRICS.time_stage(ROUTE, "encode", || encode_png(&new_image))?;
+
+                buffers.push(buf);
+            }
+
+            Ok(buffers)
+        })
+        .await?;
 
     let mut tags = Vec::with_capacity(30);
     for image in buffers {
@@ -7,25 +7,41 @@ use resvg::{
 use serde::Deserialize;
 use tiny_skia::{Pixmap, Transform};
 
-use crate::{cache::ImageCache, encoder::encode_png, error::Result};
+use crate::{
+    cache::ImageCache, encoder::encode_png, error::Result, metrics::METRICS, pool::RenderPool,
+};
+
+const ROUTE: &str = "/api/genchess";
 
 #[derive(Deserialize)]
 pub struct ChessJson {
     xml: String, // SVG
 }
 
-pub fn genchess(body: &ChessJson, images: &ImageCache) -> Result<Response<Body>> {
-    let xml = &body.xml;
-    let tree = Tree::from_str(xml, &Options::default())?;
+pub async fn genchess(
+    body: ChessJson,
+    pool: &RenderPool,
+    images: &ImageCache,
+) -> Result<Response<Body>> {
+    let final_image = pool
+        .run(move || {
+            let xml = &body.xml;
+            let tree =
+                METRICS.time_stage(ROUTE, "decode", || Tree::from_str(xml, &Options::default()))?;
+
+            let image = METRICS.time_stage(ROUTE, "composite", || {
+                // SAFETY: This only errors if width or height are 0
+                let mut map = Pixmap::new(390, 390).unwrap();
+                ResvgTree::from_usvg(&tree).render(Transform::default(), &mut map.as_mut());
 
-    // SAFETY: This only errors if width or height are 0
-    let mut map = Pixmap::new(390, 390).unwrap();
-    ResvgTree::from_usvg(&tree).render(Transform::default(), &mut map.as_mut());
+                let vect = map.take();
+                // SAFETY: Only returns None if container too small
+                RgbaImage::from_raw(390, 390, vect).unwrap()
+            });
 
-    let vect = map.take();
-    // SAFETY: Only returns None if container too small
-    let image = RgbaImage::from_raw(390, 390, vect).unwrap();
-    let final_image = encode_png(&image)?;
+            Ok(METRICS.time_stage(ROUTE, "encode", || encode_png(&image))?)
+        })
+        .await?;
 
     let tag = images.insert(final_image);
 
@@ -0,0 +1,81 @@
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+
+use crate::{cache::ImageCache, constants::loaded_assets, error::Result, pool::RenderPool};
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Serialize)]
+struct Checks {
+    assets: Check,
+    cache: Check,
+    render_pool: Check,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    checks: Checks,
+}
+
+pub fn healthz() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(Body::from("{\"status\": \"ok\"}"))?)
+}
+
+pub fn readyz(images: &ImageCache, pool: &RenderPool) -> Result<Response<Body>> {
+    let assets = loaded_assets();
+    let assets = Check {
+        ok: assets > 0,
+        detail: if assets > 0 {
+            format!("{assets} assets decoded")
+        } else {
+            String::from("assets are still being decoded")
+        },
+    };
+
+    let cache_ok = images.probe();
+    let cache = Check {
+        ok: cache_ok,
+        detail: String::from(if cache_ok {
+            "cache accepts writes"
+        } else {
+            "cache rejected a write"
+        }),
+    };
+
+    let queued = pool.queued();
+    let render_pool = Check {
+        ok: queued < pool.capacity(),
+        detail: format!(
+            "{queued} of {} queue slots used, {} jobs running",
+            pool.capacity(),
+            pool.busy()
+        ),
+    };
+
+    let ready = assets.ok && cache.ok && render_pool.ok;
+    let body = Readiness {
+        status: if ready { "ok" } else { "unavailable" },
+        checks: Checks {
+            assets,
+            cache,
+            render_pool,
+        },
+    };
+
+    Ok(Response::builder()
+        .status(if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        })
+        .header("content-type", "application/json")
+        .body(Body::from(simd_json::to_string(&body)?))?)
+}
@@ -1,72 +1,894 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{fmt::Display, io::Read, ops::RangeInclusive, sync::Arc};
 
 use hyper::{Body, Response};
 use image::{
     imageops::{invert, resize, FilterType},
-    load_from_memory, Pixel, Rgba,
+    DynamicImage, Rgba,
 };
 use imageproc_lite::canny;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 
-use crate::{cache::ImageCache, encoder::encode_png, error::Result, fetcher::Fetcher};
+use crate::{
+    cache::ImageCache,
+    constants::PIPELINE_BUDGET,
+    decoder::decode,
+    encoder::encode_dynamic_png,
+    error::{Error, Result},
+    fetcher::Fetcher,
+    filters::{
+        color::{saturate, sepia},
+        geometry::{downscale_area, rotate, rotated_size},
+        oil::oil,
+        stylize::{
+            deep_fry, dither_diffuse, dither_ordered, glitch, posterize, HANDHELD, MONOCHROME,
+            RETRO,
+        },
+    },
+    metrics::METRICS,
+    pool::RenderPool,
+};
+
+/// Largest width or height an operation may resize or grow an image to.
+/// Larger images that operations keep the size of are bounded by
+/// `MAX_IMAGE_DIMENSION` instead.
+const MAX_DIMENSION: u32 = 2048;
+
+/// Largest number of operations in a pipeline.
+const MAX_STEPS: usize = 16;
 
 #[derive(Deserialize)]
-pub struct ImageJson {
+pub struct ImageJson<P> {
     image: String, // URL
+    #[serde(flatten)]
+    params: P,
 }
 
-struct Intensity {
-    val: i32,
-    r: i32,
-    g: i32,
-    b: i32,
+/// An image operation together with its parameters.
+pub trait Operation {
+    /// Checks that all parameters are within their bounds.
+    fn validate(&self) -> Result<()>;
+
+    /// Estimates the work of applying the operation to a `width`x`height`
+    /// image, in pixels touched by a simple per-pixel operation.
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage>;
 }
 
-pub async fn pixelate(
-    body: ImageJson,
-    fetcher: Arc<Fetcher>,
-    images: &ImageCache,
-) -> Result<Response<Body>> {
-    let res = fetcher.fetch(&body.image).await?;
-    let img = load_from_memory(&res)?;
-    let buf = resize(&img, 1024, 1024, FilterType::Nearest);
-    let final_image = encode_png(&buf)?;
+/// Returns an error naming `field` unless `value` is within `range`.
+pub fn check_range<T: PartialOrd + Display>(
+    field: &str,
+    value: &T,
+    range: RangeInclusive<T>,
+) -> Result<()> {
+    if range.contains(value) {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter(format!(
+            "{field} must be between {} and {}, got {value}",
+            range.start(),
+            range.end()
+        )))
+    }
+}
 
-    let tag = images.insert(final_image);
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Pixel {
+    blocks: Option<u32>,
+    block_size: Option<u32>,
+    size: u32,
+}
 
-    Ok(Response::builder()
-        .status(200)
-        .header("content-type", "text/plain")
-        .body(Body::from(tag))?)
+impl Default for Pixel {
+    fn default() -> Self {
+        Self {
+            blocks: None,
+            block_size: None,
+            size: 1024,
+        }
+    }
+}
+
+impl Pixel {
+    /// Number of blocks along the longer side of a `longer` pixels long image.
+    fn blocks(&self, longer: u32) -> u32 {
+        let blocks = self
+            .block_size
+            .map_or_else(|| self.blocks.unwrap_or(32), |size| longer.div_ceil(size));
+
+        blocks.clamp(1, longer.min(self.size))
+    }
+}
+
+impl Operation for Pixel {
+    fn validate(&self) -> Result<()> {
+        if self.blocks.is_some() && self.block_size.is_some() {
+            return Err(Error::InvalidParameter(String::from(
+                "only one of blocks and block_size may be set",
+            )));
+        }
+
+        if let Some(blocks) = &self.blocks {
+            check_range("blocks", blocks, 1..=512)?;
+        }
+
+        if let Some(block_size) = &self.block_size {
+            check_range("block_size", block_size, 1..=512)?;
+        }
+
+        check_range("size", &self.size, 1..=MAX_DIMENSION)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        u64::from(width) * u64::from(height) + u64::from(self.size).pow(2)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let (width, height) = (img.width(), img.height());
+        let longer = width.max(height).max(1);
+        let blocks = self.blocks(longer);
+
+        // Keeps the aspect ratio of the original image
+        let scaled = |side: u32| {
+            let rounding = u64::from(longer) / 2;
+            ((u64::from(side) * u64::from(blocks) + rounding) / u64::from(longer)).max(1) as u32
+        };
+        let (blocks_x, blocks_y) = (scaled(width), scaled(height));
+
+        // Every block is the same whole number of pixels wide
+        let block = self.size / blocks;
+
+        let small = downscale_area(&img.into_rgba8(), blocks_x, blocks_y);
+
+        Ok(DynamicImage::ImageRgba8(resize(
+            &small,
+            blocks_x * block,
+            blocks_y * block,
+            FilterType::Nearest,
+        )))
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct Invert {}
+
+impl Operation for Invert {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img.into_rgba8();
+        invert(&mut img);
+
+        Ok(DynamicImage::ImageRgba8(img))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Edges {
+    low_threshold: f32,
+    high_threshold: f32,
+}
+
+impl Default for Edges {
+    fn default() -> Self {
+        Self {
+            low_threshold: 25.0,
+            high_threshold: 80.0,
+        }
+    }
+}
+
+impl Operation for Edges {
+    fn validate(&self) -> Result<()> {
+        check_range("low_threshold", &self.low_threshold, 0.0..=1000.0)?;
+        check_range(
+            "high_threshold",
+            &self.high_threshold,
+            self.low_threshold..=1000.0,
+        )
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        8 * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(DynamicImage::ImageLuma8(canny(
+            &img.into_luma8(),
+            self.low_threshold,
+            self.high_threshold,
+        )))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Oil {
+    radius: u32,
+    levels: u32,
+}
+
+impl Default for Oil {
+    fn default() -> Self {
+        Self {
+            radius: 4,
+            levels: 55,
+        }
+    }
+}
+
+impl Operation for Oil {
+    fn validate(&self) -> Result<()> {
+        check_range("radius", &self.radius, 1..=16)?;
+        check_range("levels", &self.levels, 1..=255)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        u64::from(self.radius * 2 + 2) * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(DynamicImage::ImageRgba8(oil(
+            &img.into_rgba8(),
+            self.radius,
+            self.levels,
+        )))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Blur {
+    sigma: f32,
+}
+
+impl Default for Blur {
+    fn default() -> Self {
+        Self { sigma: 3.0 }
+    }
+}
+
+impl Operation for Blur {
+    fn validate(&self) -> Result<()> {
+        check_range("sigma", &self.sigma, 0.1..=25.0)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        // Two passes with a kernel of about 6 sigma
+        (self.sigma * 12.0).ceil() as u64 * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(img.blur(self.sigma))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Sharpen {
+    sigma: f32,
+    threshold: i32,
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Self {
+            sigma: 1.5,
+            threshold: 2,
+        }
+    }
+}
+
+impl Operation for Sharpen {
+    fn validate(&self) -> Result<()> {
+        check_range("sigma", &self.sigma, 0.1..=25.0)?;
+        check_range("threshold", &self.threshold, 0..=255)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        ((self.sigma * 12.0).ceil() as u64 + 1) * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(img.unsharpen(self.sigma, self.threshold))
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct Grayscale {}
+
+impl Operation for Grayscale {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(img.grayscale())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Sepia {
+    intensity: f32,
+}
+
+impl Default for Sepia {
+    fn default() -> Self {
+        Self { intensity: 1.0 }
+    }
+}
+
+impl Operation for Sepia {
+    fn validate(&self) -> Result<()> {
+        check_range("intensity", &self.intensity, 0.0..=1.0)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img.into_rgba8();
+        sepia(&mut img, self.intensity);
+
+        Ok(DynamicImage::ImageRgba8(img))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Brightness {
+    value: i32,
+}
+
+impl Default for Brightness {
+    fn default() -> Self {
+        Self { value: 30 }
+    }
+}
+
+impl Operation for Brightness {
+    fn validate(&self) -> Result<()> {
+        check_range("value", &self.value, -255..=255)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(img.brighten(self.value))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Contrast {
+    value: f32,
+}
+
+impl Default for Contrast {
+    fn default() -> Self {
+        Self { value: 30.0 }
+    }
+}
+
+impl Operation for Contrast {
+    fn validate(&self) -> Result<()> {
+        check_range("value", &self.value, -100.0..=100.0)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(img.adjust_contrast(self.value))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Saturation {
+    factor: f32,
+}
+
+impl Default for Saturation {
+    fn default() -> Self {
+        Self { factor: 1.5 }
+    }
+}
+
+impl Operation for Saturation {
+    fn validate(&self) -> Result<()> {
+        check_range("factor", &self.factor, 0.0..=10.0)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img.into_rgba8();
+        saturate(&mut img, self.factor);
+
+        Ok(DynamicImage::ImageRgba8(img))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Hue {
+    degrees: i32,
+}
+
+impl Default for Hue {
+    fn default() -> Self {
+        Self { degrees: 90 }
+    }
 }
 
-pub async fn invert_endpoint(
-    body: ImageJson,
+impl Operation for Hue {
+    fn validate(&self) -> Result<()> {
+        check_range("degrees", &self.degrees, -360..=360)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(img.huerotate(self.degrees))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Rotate {
+    degrees: f64,
+    background: (u8, u8, u8, f32), // RGBA
+}
+
+impl Default for Rotate {
+    fn default() -> Self {
+        Self {
+            degrees: 90.0,
+            background: (0, 0, 0, 0.0),
+        }
+    }
+}
+
+impl Operation for Rotate {
+    fn validate(&self) -> Result<()> {
+        check_range("degrees", &self.degrees, -360.0..=360.0)?;
+        check_range("background alpha", &self.background.3, 0.0..=1.0)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        // Bilinear sampling on a canvas up to twice the size
+        8 * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        // Quarter turns are lossless and need no background
+        if (self.degrees % 90.0).abs() < f64::EPSILON {
+            return Ok(match ((self.degrees / 90.0).round() as i64).rem_euclid(4) {
+                1 => img.rotate90(),
+                2 => img.rotate180(),
+                3 => img.rotate270(),
+                _ => img,
+            });
+        }
+
+        let (width, height) = rotated_size(img.width(), img.height(), self.degrees);
+        let limit = MAX_DIMENSION.max(img.width()).max(img.height());
+
+        if width > limit || height > limit {
+            return Err(Error::InvalidParameter(format!(
+                "the rotated image would be {width}x{height} pixels, more than {limit} on a side"
+            )));
+        }
+
+        let (r, g, b, a) = self.background;
+        let background = Rgba([r, g, b, (a * 255.0) as u8]);
+
+        Ok(DynamicImage::ImageRgba8(rotate(
+            &img.into_rgba8(),
+            self.degrees,
+            background,
+        )))
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Flip {
+    direction: Direction,
+}
+
+impl Operation for Flip {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(match self.direction {
+            Direction::Horizontal => img.fliph(),
+            Direction::Vertical => img.flipv(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Crop {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Operation for Crop {
+    fn validate(&self) -> Result<()> {
+        check_range("width", &self.width, 1..=MAX_DIMENSION)?;
+        check_range("height", &self.height, 1..=MAX_DIMENSION)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        if self.x >= img.width() || self.y >= img.height() {
+            return Err(Error::InvalidParameter(format!(
+                "crop area starts outside of the {}x{} image",
+                img.width(),
+                img.height()
+            )));
+        }
+
+        // Parts of the area outside of the image are left out
+        Ok(img.crop_imm(self.x, self.y, self.width, self.height))
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scales the image to fit within the size, keeping the aspect ratio.
+    #[default]
+    Contain,
+    /// Scales the image to cover the size, keeping the aspect ratio, and
+    /// crops what sticks out.
+    Cover,
+    /// Stretches the image to the size.
+    Fill,
+}
+
+#[derive(Deserialize)]
+pub struct Resize {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    fit: Fit,
+}
+
+impl Operation for Resize {
+    fn validate(&self) -> Result<()> {
+        check_range("width", &self.width, 1..=MAX_DIMENSION)?;
+        check_range("height", &self.height, 1..=MAX_DIMENSION)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        let pixels = (u64::from(width) * u64::from(height))
+            .max(u64::from(self.width) * u64::from(self.height));
+
+        // Lanczos3 samples 6 pixels in each direction
+        12 * pixels
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(match self.fit {
+            Fit::Contain => img.resize(self.width, self.height, FilterType::Lanczos3),
+            Fit::Cover => img.resize_to_fill(self.width, self.height, FilterType::Lanczos3),
+            Fit::Fill => img.resize_exact(self.width, self.height, FilterType::Lanczos3),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Posterize {
+    levels: u8,
+}
+
+impl Default for Posterize {
+    fn default() -> Self {
+        Self { levels: 4 }
+    }
+}
+
+impl Operation for Posterize {
+    fn validate(&self) -> Result<()> {
+        check_range("levels", &self.levels, 2..=64)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img.into_rgba8();
+        posterize(&mut img, self.levels);
+
+        Ok(DynamicImage::ImageRgba8(img))
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NamedPalette {
+    #[default]
+    Retro,
+    Handheld,
+    Monochrome,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Palette {
+    Named(NamedPalette),
+    Colors(Vec<[u8; 3]>), // RGB
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::Named(NamedPalette::default())
+    }
+}
+
+impl Palette {
+    fn colors(&self) -> &[[u8; 3]] {
+        match self {
+            Self::Named(NamedPalette::Retro) => &RETRO,
+            Self::Named(NamedPalette::Handheld) => &HANDHELD,
+            Self::Named(NamedPalette::Monochrome) => &MONOCHROME,
+            Self::Colors(colors) => colors,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub enum DitherMethod {
+    #[default]
+    #[serde(rename = "floyd-steinberg")]
+    FloydSteinberg,
+    #[serde(rename = "ordered")]
+    Ordered,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Dither {
+    palette: Palette,
+    method: DitherMethod,
+}
+
+impl Operation for Dither {
+    fn validate(&self) -> Result<()> {
+        check_range("palette size", &self.palette.colors().len(), 2..=256)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        // Every pixel is compared to every palette color
+        (self.palette.colors().len() as u64 / 4 + 2) * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img.into_rgba8();
+
+        match self.method {
+            DitherMethod::FloydSteinberg => dither_diffuse(&mut img, self.palette.colors()),
+            DitherMethod::Ordered => dither_ordered(&mut img, self.palette.colors()),
+        }
+
+        Ok(DynamicImage::ImageRgba8(img))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DeepFry {
+    saturation: f32,
+    noise: u8,
+    iterations: u32,
+    quality: u8,
+    seed: u64,
+}
+
+impl Default for DeepFry {
+    fn default() -> Self {
+        Self {
+            saturation: 3.0,
+            noise: 24,
+            iterations: 5,
+            quality: 15,
+            seed: 0,
+        }
+    }
+}
+
+impl Operation for DeepFry {
+    fn validate(&self) -> Result<()> {
+        check_range("saturation", &self.saturation, 0.0..=10.0)?;
+        check_range("noise", &self.noise, 0..=128)?;
+        check_range("iterations", &self.iterations, 1..=20)?;
+        check_range("quality", &self.quality, 1..=100)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        (u64::from(self.iterations) * 8 + 4) * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img.into_rgba8();
+        deep_fry(
+            &mut img,
+            self.saturation,
+            self.noise,
+            self.iterations,
+            self.quality,
+            self.seed,
+        )?;
+
+        Ok(DynamicImage::ImageRgba8(img))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Glitch {
+    shift: u32,
+    slices: u32,
+    seed: u64,
+}
+
+impl Default for Glitch {
+    fn default() -> Self {
+        Self {
+            shift: 8,
+            slices: 12,
+            seed: 0,
+        }
+    }
+}
+
+impl Operation for Glitch {
+    fn validate(&self) -> Result<()> {
+        check_range("shift", &self.shift, 0..=256)?;
+        check_range("slices", &self.slices, 0..=64)
+    }
+
+    fn cost(&self, width: u32, height: u32) -> u64 {
+        2 * u64::from(width) * u64::from(height)
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        Ok(DynamicImage::ImageRgba8(glitch(
+            &img.into_rgba8(),
+            self.shift,
+            self.slices,
+            self.seed,
+        )))
+    }
+}
+
+/// Declares every operation once, as its name and parameters. This defines
+/// [`OPERATIONS`], the pipeline [`Step`]s and the routes of [`imageops`], so
+/// that they cannot drift apart.
+macro_rules! operations {
+    ($($name:literal => $params:ident),* $(,)?) => {
+        /// Names of all operations, served under `/api/imageops/{name}`.
+        pub const OPERATIONS: &[&str] = &[$($name),*];
+
+        /// One operation of a pipeline, selected by its `op` field.
+        #[derive(Deserialize)]
+        #[serde(tag = "op")]
+        pub enum Step {
+            $(
+                #[serde(rename = $name)]
+                $params($params),
+            )*
+        }
+
+        impl Step {
+            fn operation(&self) -> &dyn Operation {
+                match self {
+                    $(Self::$params(op) => op,)*
+                }
+            }
+        }
+
+        /// Renders the operation named `operation`, or returns `None` if there
+        /// is no such operation.
+        async fn dispatch(
+            operation: &str,
+            reader: impl Read,
+            fetcher: Arc<Fetcher>,
+            pool: &RenderPool,
+            images: &ImageCache,
+        ) -> Option<Result<Response<Body>>> {
+            match operation {
+                $(
+                    $name => Some(
+                        imageop::<$params>(
+                            concat!("/api/imageops/", $name),
+                            reader,
+                            fetcher,
+                            pool,
+                            images,
+                        )
+                        .await,
+                    ),
+                )*
+                _ => None,
+            }
+        }
+    };
+}
+
+operations! {
+    "pixel" => Pixel,
+    "invert" => Invert,
+    "edges" => Edges,
+    "oil" => Oil,
+    "blur" => Blur,
+    "sharpen" => Sharpen,
+    "grayscale" => Grayscale,
+    "sepia" => Sepia,
+    "brightness" => Brightness,
+    "contrast" => Contrast,
+    "saturation" => Saturation,
+    "hue" => Hue,
+    "rotate" => Rotate,
+    "flip" => Flip,
+    "crop" => Crop,
+    "resize" => Resize,
+    "posterize" => Posterize,
+    "dither" => Dither,
+    "deepfry" => DeepFry,
+    "glitch" => Glitch,
+}
+
+#[derive(Deserialize)]
+pub struct PipelineJson {
+    image: String, // URL
+    operations: Vec<Step>,
+}
+
+/// Renders `/api/imageops/{operation}` with the given JSON body.
+pub async fn imageops(
+    operation: &str,
+    reader: impl Read,
     fetcher: Arc<Fetcher>,
+    pool: &RenderPool,
     images: &ImageCache,
 ) -> Result<Response<Body>> {
-    let res = fetcher.fetch(&body.image).await?;
-    let mut img = load_from_memory(&res)?.to_rgba8();
-    invert(&mut img);
-    let final_image = encode_png(&img)?;
-
-    let tag = images.insert(final_image);
+    if operation == "pipeline" {
+        return pipeline(reader, fetcher, pool, images).await;
+    }
 
-    Ok(Response::builder()
-        .status(200)
-        .header("content-type", "text/plain")
-        .body(Body::from(tag))?)
+    dispatch(operation, reader, fetcher, pool, images)
+        .await
+        .unwrap_or_else(|| Ok(Response::builder().status(404).body(Body::empty())?))
 }
 
-pub async fn edges_endpoint(
-    body: ImageJson,
+async fn imageop<P: Operation + DeserializeOwned + Send + 'static>(
+    route: &'static str,
+    reader: impl Read,
     fetcher: Arc<Fetcher>,
+    pool: &RenderPool,
     images: &ImageCache,
 ) -> Result<Response<Body>> {
+    let body: ImageJson<P> = simd_json::from_reader(reader)?;
+    body.params.validate()?;
+
     let res = fetcher.fetch(&body.image).await?;
-    let img = load_from_memory(&res)?.to_luma8();
-    let buf = canny(&img, 25.0, 80.0);
-    let final_image = encode_png(&buf)?;
+    let params = body.params;
+    let final_image = pool
+        .run(move || {
+            let img = METRICS.time_stage(route, "decode", || decode(&res))?;
+            let img = METRICS.time_stage(route, "composite", || params.apply(img))?;
+
+            Ok(METRICS.time_stage(route, "encode", || encode_dynamic_png(&img))?)
+        })
+        .await?;
 
     let tag = images.insert(final_image);
 
@@ -76,88 +898,54 @@ pub async fn edges_endpoint(
         .body(Body::from(tag))?)
 }
 
-pub async fn oil_endpoint(
-    body: ImageJson,
+/// Applies all operations in order to one decoded image. The work of each
+/// step is estimated before it runs, and the pipeline is aborted once the
+/// total exceeds `PIPELINE_BUDGET`.
+async fn pipeline(
+    reader: impl Read,
     fetcher: Arc<Fetcher>,
+    pool: &RenderPool,
     images: &ImageCache,
 ) -> Result<Response<Body>> {
+    const ROUTE: &str = "/api/imageops/pipeline";
+
+    let body: PipelineJson = simd_json::from_reader(reader)?;
+    check_range(
+        "number of operations",
+        &body.operations.len(),
+        1..=MAX_STEPS,
+    )?;
+
+    for step in &body.operations {
+        step.operation().validate()?;
+    }
+
     let res = fetcher.fetch(&body.image).await?;
-    let img = load_from_memory(&res)?.to_rgba8();
-
-    let radius = 4_i32;
-    let intensity = 55.0;
-    let width = img.width();
-    let height = img.height();
-    let mut target = image::RgbaImage::new(width, height);
-    let mut pixel_intensity_count: HashMap<usize, Intensity>;
-    let mut intensity_lut = vec![vec![0; width as usize]; height as usize];
-
-    for y in 0..height {
-        for x in 0..width {
-            let current_val = img.get_pixel(x, y).channels();
-            let avg = f64::from(
-                i32::from(current_val[0]) + i32::from(current_val[1]) + i32::from(current_val[2]),
-            ) / 3.0;
-            let val = (avg * intensity) / 255.0;
-            intensity_lut[y as usize][x as usize] = val.round() as usize;
-        }
-    }
-
-    for y in 0..height {
-        for x in 0..width {
-            pixel_intensity_count = HashMap::new();
-
-            // Find intensities of nearest pixels within radius
-            for yy in -radius..=radius {
-                let yyy = (y as i32) + yy;
-                for xx in -radius..=radius {
-                    let xxx = (x as i32) + xx;
-                    if yyy > 0 && yyy < (height as i32) && xxx > 0 && xxx < (width as i32) {
-                        let idx_x = xxx as usize;
-                        let idx_y = yyy as usize;
-                        let intensity_val = intensity_lut[idx_y][idx_x];
-                        let pix = img.get_pixel(idx_x as u32, idx_y as u32).channels();
-                        match pixel_intensity_count.get_mut(&intensity_val) {
-                            Some(val) => {
-                                val.val += 1;
-                                val.r += i32::from(pix[0]);
-                                val.g += i32::from(pix[1]);
-                                val.b += i32::from(pix[2]);
-                            }
-                            None => {
-                                pixel_intensity_count.insert(
-                                    intensity_val,
-                                    Intensity {
-                                        val: 1,
-                                        r: i32::from(pix[0]),
-                                        g: i32::from(pix[1]),
-                                        b: i32::from(pix[2]),
-                                    },
-                                );
-                            }
-                        }
+    let steps = body.operations;
+    let final_image = pool
+        .run(move || {
+            let img = METRICS.time_stage(ROUTE, "decode", || decode(&res))?;
+            let img = METRICS.time_stage(ROUTE, "composite", || {
+                let mut img = img;
+                let mut spent = 0;
+
+                for step in &steps {
+                    let operation = step.operation();
+
+                    spent += operation.cost(img.width(), img.height());
+                    if spent > *PIPELINE_BUDGET {
+                        return Err(Error::BudgetExceeded);
                     }
-                }
-            }
 
-            let mut map_vec: Vec<_> = pixel_intensity_count.iter().collect();
-            map_vec.sort_by(|a, b| (b.1.val - a.1.val).cmp(&0));
+                    img = operation.apply(img)?;
+                }
 
-            let cur_max = map_vec[0].1;
-            target.put_pixel(
-                x,
-                y,
-                Rgba::<u8>([
-                    (cur_max.r / cur_max.val) as u8,
-                    (cur_max.g / cur_max.val) as u8,
-                    (cur_max.b / cur_max.val) as u8,
-                    255,
-                ]),
-            );
-        }
-    }
+                Ok(img)
+            })?;
 
-    let final_image = encode_png(&target)?;
+            Ok(METRICS.time_stage(ROUTE, "encode", || encode_dynamic_png(&img))?)
+        })
+        .await?;
 
     let tag = images.insert(final_image);
 
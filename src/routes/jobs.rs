@@ -0,0 +1,31 @@
+use hyper::{Body, Response, StatusCode};
+use serde::Deserialize;
+use simd_json::OwnedValue;
+
+use crate::{error::Result, jobs::JobStore};
+
+#[derive(Deserialize)]
+pub struct JobJson {
+    pub route: String,
+    pub body: OwnedValue,
+}
+
+pub fn job_submitted(id: &str, jobs: &JobStore) -> Result<Response<Body>> {
+    job_response(id, jobs, StatusCode::ACCEPTED)
+}
+
+pub fn job_status(id: &str, jobs: &JobStore) -> Result<Response<Body>> {
+    job_response(id, jobs, StatusCode::OK)
+}
+
+fn job_response(id: &str, jobs: &JobStore, status: StatusCode) -> Result<Response<Body>> {
+    let Some(job) = jobs.get(id) else {
+        return Ok(Response::builder().status(404).body(Body::empty())?);
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header("location", format!("/api/jobs/{id}"))
+        .body(Body::from(simd_json::to_string(&job.status(id))?))?)
+}
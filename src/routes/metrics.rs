@@ -0,0 +1,10 @@
+use hyper::{Body, Response};
+
+use crate::{cache::ImageCache, error::Result, metrics::METRICS, pool::RenderPool};
+
+pub fn metrics(images: &ImageCache, pool: &RenderPool) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(Body::from(METRICS.render(images, pool)))?)
+}
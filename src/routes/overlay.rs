@@ -1,20 +1,22 @@
-use std::{io::Cursor, sync::Arc};
+use std::sync::Arc;
 
 use hyper::{Body, Response};
-use image::{
-    imageops::{overlay, resize, FilterType},
-    io::{Limits, Reader},
-};
+use image::imageops::{overlay, resize, FilterType};
 use serde::Deserialize;
 
 use crate::{
     cache::ImageCache,
     constants::{PROFILE_DARK, PROFILE_LIGHT},
+    decoder::decode,
     encoder::encode_png,
     error::Result,
     fetcher::Fetcher,
+    metrics::METRICS,
+    pool::RenderPool,
 };
 
+const ROUTE: &str = "/api/genoverlay";
+
 #[derive(Deserialize)]
 pub struct OverlayJson {
     url: String,
@@ -24,30 +26,31 @@ pub struct OverlayJson {
 pub async fn genoverlay(
     body: OverlayJson,
     fetcher: Arc<Fetcher>,
+    pool: &RenderPool,
     images: &ImageCache,
 ) -> Result<Response<Body>> {
-    let mut limits = Limits::default();
-    limits.max_image_width = Some(2000);
-    limits.max_image_height = Some(2000);
-
     let res = fetcher.fetch(&body.url).await?;
-    let b = Cursor::new(res);
-    let mut reader = Reader::new(b);
-    reader.limits(limits);
-    reader = reader.with_guessed_format()?;
 
-    let img = reader.decode()?;
+    let final_image = pool
+        .run(move || {
+            let img = METRICS.time_stage(ROUTE, "decode", || decode(&res))?;
+
+            let img = METRICS.time_stage(ROUTE, "composite", || {
+                // Lanczos3 is best, but has slow speed
+                let mut img = resize(&img, 800, 533, FilterType::Lanczos3);
 
-    // Lanczos3 is best, but has slow speed
-    let mut img = resize(&img, 800, 533, FilterType::Lanczos3);
+                if body.style == "dark" {
+                    overlay(&mut img, &PROFILE_DARK.clone(), 0, 0);
+                } else if body.style == "light" {
+                    overlay(&mut img, &PROFILE_LIGHT.clone(), 0, 0);
+                }
 
-    if body.style == "dark" {
-        overlay(&mut img, &PROFILE_DARK.clone(), 0, 0);
-    } else if body.style == "light" {
-        overlay(&mut img, &PROFILE_LIGHT.clone(), 0, 0);
-    }
+                img
+            });
 
-    let final_image = encode_png(&img)?;
+            Ok(METRICS.time_stage(ROUTE, "encode", || encode_png(&img))?)
+        })
+        .await?;
 
     let tag = images.insert(final_image);
 
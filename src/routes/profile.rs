@@ -1,21 +1,33 @@
-use std::{io::Cursor, sync::Arc};
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, sync::Arc, time::Instant};
 
 use ab_glyph::PxScale;
+use bytes::Bytes;
 use hyper::{Body, Response};
 use image::{
-    imageops::overlay,
-    io::{Limits, Reader},
-    Rgba,
+    imageops::{self, overlay, resize, FilterType},
+    GrayImage, Luma, Pixel, Rgba, RgbaImage,
 };
 use imageproc_lite::{draw_text_mut, Blend};
 use serde::Deserialize;
 
 use crate::{
     cache::ImageCache,
-    constants::{BADGES, CLASSES, DEFAULT_PROFILE, GUILD_RANKS, ITEM_TYPES, RACES, TRAVITIA_FONT},
+    constants::{BADGES, CLASSES, DEFAULT_PROFILE, GUILD_RANKS, ITEM_TYPES, LAYOUTS, RACES},
+    decoder::decode,
     encoder::encode_png,
     error::{Error, Result},
     fetcher::Fetcher,
+    filters::{
+        color::{average_luma, luma},
+        geometry::{contain_blurred, cover},
+        mask::{dilate, fill_mask},
+        shape::fill_pill,
+    },
+    layout::{Align, Anchor, BadgeArea, BarSlot, IconField, Layout, TextBox, TextField},
+    metrics::METRICS,
+    pool::RenderPool,
+    routes::imageops::check_range,
+    text::{fit_text, line_height, runs, text_width, RunKind},
 };
 
 #[derive(Deserialize)]
@@ -23,7 +35,7 @@ pub struct ProfileJson {
     name: String,
     image: String,
     race: String,
-    color: (u8, u8, u8, f32), // RGBA
+    color: TextColor,
     classes: Vec<String>,
     class_icons: Vec<String>,
     left_hand_item: Option<(String, String, String)>, // Type, Name, Stat
@@ -38,289 +50,573 @@ pub struct ProfileJson {
     adventure_name: Option<String>,
     adventure_time: Option<String>,
     badges: Vec<String>,
+    layout: Option<String>,
+    outline: Option<Outline>,
+    shadow: Option<Shadow>,
+    #[serde(default)]
+    fit: BackgroundFit,
+    focus: Option<(f64, f64)>, // X, Y
+    #[serde(default)]
+    bars: HashMap<String, Bar>,
 }
 
-const PX_52: PxScale = PxScale { x: 52.0, y: 52.0 };
-const PX_34: PxScale = PxScale { x: 34.0, y: 34.0 };
-const PX_30: PxScale = PxScale { x: 30.0, y: 30.0 };
-const PX_22: PxScale = PxScale { x: 22.0, y: 22.0 };
-
-const BADGE_X_VALUES: [i64; 8] = [50, 144, 234, 327, 422, 513, 616, 712];
+/// A progress bar, drawn into the layout slot of the same name.
+#[derive(Deserialize)]
+struct Bar {
+    current: f64,
+    max: f64,
+    /// Defaults to `current / max`.
+    label: Option<String>,
+    color: Option<(u8, u8, u8, f32)>, // RGBA
+}
 
-pub async fn genprofile(
-    body: ProfileJson,
-    fetcher: Arc<Fetcher>,
-    images: &ImageCache,
-) -> Result<Response<Body>> {
-    let image_url = &body.image;
+/// How custom backgrounds are fitted to the size of the profile card.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum BackgroundFit {
+    /// Scales the background to cover the card and crops what sticks out,
+    /// keeping the focus point as close to the center as possible.
+    #[default]
+    Cover,
+    /// Scales the background to fit within the card, on top of a blurred
+    /// copy of itself.
+    Contain,
+    /// Stretches the background to the card.
+    Fill,
+}
 
-    let mut img = if image_url == "0" {
-        DEFAULT_PROFILE.clone()
-    } else {
-        let mut limits = Limits::default();
-        limits.max_image_width = Some(2000);
-        limits.max_image_height = Some(2000);
+/// Color of the profile texts.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum TextColor {
+    Rgba((u8, u8, u8, f32)),
+    /// Black or white for every text, whichever contrasts more with the
+    /// background below it.
+    Auto(Auto),
+}
 
-        let buf = fetcher.fetch(image_url).await?;
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Auto {
+    Auto,
+}
 
-        let b = Cursor::new(buf);
+/// Outline around the profile texts. The color defaults to black or white,
+/// whichever contrasts more with the text.
+#[derive(Deserialize)]
+#[serde(default)]
+struct Outline {
+    color: Option<(u8, u8, u8, f32)>, // RGBA
+    width: u32,
+}
 
-        let mut reader = Reader::new(b);
-        reader.limits(limits);
-        reader = reader.with_guessed_format()?;
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            color: None,
+            width: 2,
+        }
+    }
+}
 
-        let image = reader.decode()?.to_rgba8();
+/// Drop shadow below the profile texts. The color defaults to black or white,
+/// whichever contrasts more with the text, at 75% opacity.
+#[derive(Deserialize)]
+#[serde(default)]
+struct Shadow {
+    color: Option<(u8, u8, u8, f32)>, // RGBA
+    x: i32,
+    y: i32,
+    blur: f32,
+}
 
-        if image.height() < 533 || image.width() < 800 {
-            return Err(Error::ImageTooSmall);
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            color: None,
+            x: 2,
+            y: 2,
+            blur: 1.5,
         }
+    }
+}
 
-        image
-    };
+const ROUTE: &str = "/api/genprofile";
+
+const MAX_BADGES: usize = 32;
 
-    overlay(&mut img, &RACES[&body.race.to_lowercase().as_str()], 6, 150);
+const BAR_COLOR: Rgba<u8> = Rgba([76, 175, 80, 255]);
+const BAR_TRACK: Rgba<u8> = Rgba([0, 0, 0, 112]);
 
-    let class_icon_1 = &body.class_icons[0];
-    let class_icon_2 = &body.class_icons[1];
+/// The layout and icons of a profile, looked up before anything is
+/// downloaded.
+struct Icons {
+    layout: &'static Layout,
+    race: &'static RgbaImage,
+    classes: Vec<Option<&'static RgbaImage>>,
+    guild_rank: Option<&'static RgbaImage>,
+    right_hand_item: Option<&'static RgbaImage>,
+    left_hand_item: Option<&'static RgbaImage>,
+    badges: Vec<&'static RgbaImage>,
+}
 
-    if class_icon_1 != "none" {
-        overlay(&mut img, &CLASSES[class_icon_1.as_str()], 6, 244);
+impl Icons {
+    fn get(&self, field: IconField) -> Option<&'static RgbaImage> {
+        match field {
+            IconField::Race => Some(self.race),
+            IconField::PrimaryClass => self.classes.first().copied().flatten(),
+            IconField::SecondaryClass => self.classes.get(1).copied().flatten(),
+            IconField::GuildRank => self.guild_rank,
+            IconField::RightHandItem => self.right_hand_item,
+            IconField::LeftHandItem => self.left_hand_item,
+        }
     }
+}
 
-    if class_icon_2 != "none" {
-        overlay(&mut img, &CLASSES[class_icon_2.as_str()], 6, 300);
+impl ProfileJson {
+    fn text(&self, field: TextField) -> Option<String> {
+        let item_name = |item: &Option<(String, String, String)>| {
+            item.as_ref().map(|(_, name, _)| name.clone())
+        };
+        let item_stat = |item: &Option<(String, String, String)>| {
+            item.as_ref().map(|(_, _, stat)| stat.clone())
+        };
+
+        match field {
+            TextField::CharacterName => Some(self.guild_name.as_ref().map_or_else(
+                || self.name.clone(),
+                |guild_name| format!("{} of {}", self.name, guild_name),
+            )),
+            TextField::Level => Some(self.level.clone()),
+            TextField::Marriage => self
+                .marriage
+                .as_ref()
+                .map(|marriage| format!("married to {marriage}")),
+            TextField::Race => Some(self.race.clone()),
+            TextField::PrimaryClass => self.classes.first().cloned(),
+            TextField::SecondaryClass => self
+                .classes
+                .get(1)
+                .filter(|class| *class != "No Class")
+                .cloned(),
+            TextField::Money => Some(self.money.clone()),
+            TextField::PvpWins => Some(self.pvp_wins.clone()),
+            TextField::God => Some(self.god.clone()),
+            TextField::AdventureName => Some(
+                self.adventure_name
+                    .clone()
+                    .unwrap_or_else(|| String::from("No Adventure")),
+            ),
+            TextField::AdventureTime => self.adventure_time.clone(),
+            TextField::RightHandItemName => item_name(&self.right_hand_item),
+            TextField::RightHandItemStat => item_stat(&self.right_hand_item),
+            TextField::LeftHandItemName => item_name(&self.left_hand_item),
+            TextField::LeftHandItemStat => item_stat(&self.left_hand_item),
+        }
     }
+}
 
-    if let Some(rank) = body.guild_rank {
-        overlay(
-            &mut img,
-            &GUILD_RANKS[&rank.to_lowercase().as_str()],
-            610,
-            3,
-        );
+fn lookup<K: Borrow<str> + Eq + Hash, T>(
+    values: &'static HashMap<K, T>,
+    field: impl Into<String>,
+    key: &str,
+) -> Result<&'static T> {
+    values.get(key).ok_or_else(|| {
+        let mut valid: Vec<&'static str> = values.keys().map(Borrow::borrow).collect();
+        valid.sort_unstable();
+
+        Error::InvalidValue {
+            field: field.into(),
+            valid,
+        }
+    })
+}
+
+fn check_len<T>(field: &str, values: &[T], max: usize) -> Result<()> {
+    if values.len() > max {
+        return Err(Error::InvalidParameter(format!(
+            "{field} may have at most {max} entries, got {}",
+            values.len()
+        )));
     }
 
-    if let Some((item_type, _, _)) = &body.right_hand_item {
-        overlay(
-            &mut img,
-            &ITEM_TYPES[&item_type.to_lowercase().as_str()],
-            262,
-            117,
-        );
+    Ok(())
+}
+
+/// Checks every key in the request and looks up the layout and the matching
+/// icons.
+fn resolve(body: &ProfileJson) -> Result<Icons> {
+    check_len("classes", &body.classes, 2)?;
+    check_len("class_icons", &body.class_icons, 2)?;
+    check_len("badges", &body.badges, MAX_BADGES)?;
+
+    let layout = lookup(
+        &LAYOUTS,
+        "layout",
+        body.layout.as_deref().unwrap_or("default"),
+    )?;
+
+    for (name, bar) in &body.bars {
+        if !layout.bars.iter().any(|slot| slot.name == *name) {
+            let mut valid: Vec<&'static str> =
+                layout.bars.iter().map(|slot| slot.name.as_str()).collect();
+            valid.sort_unstable();
+
+            return Err(Error::InvalidValue {
+                field: String::from("bars"),
+                valid,
+            });
+        }
+
+        if bar.max <= 0.0 {
+            return Err(Error::InvalidParameter(format!(
+                "bars.{name}.max must be positive, got {}",
+                bar.max
+            )));
+        }
     }
 
-    if let Some((item_type, _, _)) = &body.left_hand_item {
-        overlay(
-            &mut img,
-            &ITEM_TYPES[&item_type.to_lowercase().as_str()],
-            262,
-            188,
-        );
+    if let Some((x, y)) = body.focus {
+        check_range("focus[0]", &x, 0.0..=1.0)?;
+        check_range("focus[1]", &y, 0.0..=1.0)?;
     }
 
-    for (index, badge) in body.badges.iter().enumerate() {
-        overlay(
-            &mut img,
-            &BADGES[badge.as_str()],
-            BADGE_X_VALUES[index],
-            482,
-        );
+    if let Some(outline) = &body.outline {
+        check_range("outline.width", &outline.width, 1..=8)?;
     }
 
-    let mut blend = Blend(img);
+    if let Some(shadow) = &body.shadow {
+        check_range("shadow.x", &shadow.x, -16..=16)?;
+        check_range("shadow.y", &shadow.y, -16..=16)?;
+        check_range("shadow.blur", &shadow.blur, 0.0..=8.0)?;
+    }
+
+    let item = |field: &str, item: &Option<(String, String, String)>| {
+        item.as_ref()
+            .map(|(item_type, _, _)| lookup(&ITEM_TYPES, field, &item_type.to_lowercase()))
+            .transpose()
+    };
 
-    let r = body.color.0;
-    let g = body.color.1;
-    let b = body.color.2;
-    let a = (body.color.3 * 255.0) as u8;
-    let color = Rgba([r, g, b, a]);
+    Ok(Icons {
+        layout,
+        race: lookup(&RACES, "race", &body.race.to_lowercase())?,
+        classes: body
+            .class_icons
+            .iter()
+            .enumerate()
+            .map(|(index, icon)| {
+                (icon != "none")
+                    .then(|| lookup(&CLASSES, format!("class_icons[{index}]"), icon))
+                    .transpose()
+            })
+            .collect::<Result<_>>()?,
+        guild_rank: body
+            .guild_rank
+            .as_ref()
+            .map(|rank| lookup(&GUILD_RANKS, "guild_rank", &rank.to_lowercase()))
+            .transpose()?,
+        right_hand_item: item("right_hand_item", &body.right_hand_item)?,
+        left_hand_item: item("left_hand_item", &body.left_hand_item)?,
+        badges: body
+            .badges
+            .iter()
+            .enumerate()
+            .map(|(index, badge)| lookup(&BADGES, format!("badges[{index}]"), badge))
+            .collect::<Result<_>>()?,
+    })
+}
 
-    let character_name = if let Some(guild_name) = body.guild_name {
-        format!("{} of {}", body.name, guild_name)
-    } else {
-        body.name
+/// Places `count` badges of at most `width`x`height` pixels in the badge area.
+/// Up to `per_row` badges are spaced evenly in a single row, or placed at the
+/// `columns` of the area if it has enough. More are shrunk to fit, wrapping
+/// into further rows whenever that lets them be larger.
+///
+/// Returns the scale of the badges and their positions.
+fn badge_layout(area: &BadgeArea, count: usize, width: u32, height: u32) -> (f64, Vec<(i64, i64)>) {
+    let count = count as u32;
+    let area_width = area.width.max(1);
+    let area_height = area.height.max(1);
+    let width = width.clamp(1, area_width);
+    let height = height.clamp(1, area_height);
+
+    if count as usize <= area.columns.len() {
+        let top = i64::from((area_height - height) / 2);
+        let positions = area.columns[..count as usize]
+            .iter()
+            .map(|column| (area.x + i64::from(*column), area.y + top))
+            .collect();
+
+        return (1.0, positions);
+    }
+
+    let pitch = f64::from(area_width - width) / f64::from(area.per_row.max(2) - 1);
+
+    let fit = |rows: u32| {
+        let per_row = count.div_ceil(rows).max(1);
+        let scale = (f64::from(area_height) / f64::from(rows * height))
+            .min(f64::from(area_width) / pitch.mul_add(f64::from(per_row - 1), f64::from(width)))
+            .min(1.0);
+
+        (scale, per_row)
     };
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        12,
-        12,
-        PX_52,
-        550,
-        &*TRAVITIA_FONT,
-        &character_name,
-    );
+    let (mut scale, mut per_row) = fit(1);
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        720,
-        16,
-        PX_52,
-        70,
-        &*TRAVITIA_FONT,
-        &body.level,
-    );
+    for rows in 2..=count.max(1) {
+        let (row_scale, row_per_row) = fit(rows);
 
-    if let Some(marriage) = body.marriage {
-        let text = format!("married to {marriage}");
-
-        draw_text_mut(
-            &mut blend,
-            color,
-            180,
-            76,
-            PX_22,
-            500,
-            &*TRAVITIA_FONT,
-            &text,
-        );
+        if row_scale > scale {
+            (scale, per_row) = (row_scale, row_per_row);
+        }
     }
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        70,
-        168,
-        PX_34,
-        160,
-        &*TRAVITIA_FONT,
-        &body.race,
-    );
+    // Centers the rows in the area
+    let rows = count.div_ceil(per_row);
+    let top = (f64::from(area_height) - f64::from(rows * height) * scale) / 2.0;
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        70,
-        263,
-        PX_34,
-        160,
-        &*TRAVITIA_FONT,
-        &body.classes[0],
-    );
+    let positions = (0..count)
+        .map(|index| {
+            let (row, column) = (index / per_row, index % per_row);
 
-    if body.classes[1] != "No Class" {
-        draw_text_mut(
-            &mut blend,
-            color,
-            70,
-            320,
-            PX_34,
-            160,
-            &*TRAVITIA_FONT,
-            &body.classes[1],
-        );
+            (
+                area.x + (f64::from(column) * pitch * scale).round() as i64,
+                area.y + f64::from(row * height).mul_add(scale, top).round() as i64,
+            )
+        })
+        .collect();
+
+    (scale, positions)
+}
+
+/// Black or white, whichever contrasts more with a color of the given luma.
+fn contrasting(luma: f32) -> Rgba<u8> {
+    if luma > 127.5 {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
     }
+}
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        650,
-        283,
-        PX_30,
-        140,
-        &*TRAVITIA_FONT,
-        &body.money,
-    );
+const fn to_rgba((r, g, b, a): (u8, u8, u8, f32)) -> Rgba<u8> {
+    Rgba([r, g, b, (a * 255.0) as u8])
+}
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        650,
-        332,
-        PX_30,
-        140,
-        &*TRAVITIA_FONT,
-        &body.pvp_wins,
+/// Draws `text` into its slot, aligned relative to the slot position, with
+/// the shadow and outline of the profile below it.
+fn draw_slot(canvas: &mut Blend<RgbaImage>, body: &ProfileJson, slot: &TextBox, text: &str) {
+    let fonts = slot.font.fonts();
+    let (scale, text) = fit_text(
+        fonts,
+        PxScale::from(slot.size),
+        PxScale::from(slot.min_size.unwrap_or(slot.size)),
+        slot.max_width,
+        text,
     );
 
-    draw_text_mut(
-        &mut blend,
-        color,
-        650,
-        381,
-        PX_30,
-        140,
-        &*TRAVITIA_FONT,
-        &body.god,
-    );
+    let width = (text_width(fonts, scale, &text) as i32).min(slot.max_width);
+    let height = line_height(fonts, PxScale::from(slot.size)) as i32;
+    // Shrunk text stays vertically centered on the line it was made for
+    let shrink = (height - line_height(fonts, scale) as i32) / 2;
 
-    let adventure_text = body
-        .adventure_name
-        .unwrap_or_else(|| String::from("No Adventure"));
-
-    draw_text_mut(
-        &mut blend,
-        color,
-        345,
-        298,
-        PX_34,
-        210,
-        &*TRAVITIA_FONT,
-        &adventure_text,
-    );
+    let x = match slot.align {
+        Align::Left => slot.x,
+        Align::Center => slot.x - width / 2,
+        Align::Right => slot.x - width,
+    };
+    let top = match slot.anchor {
+        Anchor::Top => slot.y,
+        Anchor::Middle => slot.y - height / 2,
+        Anchor::Bottom => slot.y - height,
+    };
+    let y = top + shrink;
+
+    let color = match body.color {
+        TextColor::Rgba(color) => to_rgba(color),
+        TextColor::Auto(_) => contrasting(
+            average_luma(
+                &canvas.0,
+                i64::from(x),
+                i64::from(top),
+                width as u32,
+                height as u32,
+            )
+            .unwrap_or_default(),
+        ),
+    };
+    let contrast = contrasting(luma(
+        f32::from(color[0]),
+        f32::from(color[1]),
+        f32::from(color[2]),
+    ));
+
+    let runs = runs(fonts, scale, &text);
+    let emojis: Vec<Option<Arc<RgbaImage>>> = runs
+        .iter()
+        .map(|run| match run.kind {
+            RunKind::Emoji { sprite, size } => sprite.render(size),
+            RunKind::Text { .. } => None,
+        })
+        .collect();
+
+    if body.outline.is_some() || body.shadow.is_some() {
+        let outline = body.outline.as_ref().map_or(0, |outline| outline.width);
+        let blur = body.shadow.as_ref().map_or(0.0, |shadow| shadow.blur);
+        // Leaves room for the outline and the blurred edges of the shadow
+        let pad = outline + (blur * 3.0).ceil() as u32;
+
+        let mut mask = GrayImage::new(width as u32 + pad * 2, height as u32 + pad * 2);
+
+        for (run, emoji) in runs.iter().zip(&emojis) {
+            let (run_x, run_y) = (pad as i32 + run.x, pad as i32 + shrink + run.y);
+
+            match (&run.kind, emoji) {
+                (RunKind::Text { font, text }, _) => draw_text_mut(
+                    &mut mask,
+                    Luma([255]),
+                    run_x,
+                    run_y,
+                    scale,
+                    slot.max_width - run.x,
+                    *font,
+                    text,
+                ),
+                (RunKind::Emoji { .. }, Some(emoji)) => {
+                    for (emoji_x, emoji_y, pixel) in emoji.enumerate_pixels() {
+                        let (mask_x, mask_y) = (run_x + emoji_x as i32, run_y + emoji_y as i32);
+
+                        if let Some(coverage) =
+                            mask.get_pixel_mut_checked(mask_x as u32, mask_y as u32)
+                        {
+                            coverage[0] = coverage[0].max(pixel[3]);
+                        }
+                    }
+                }
+                (RunKind::Emoji { .. }, None) => {}
+            }
+        }
 
-    if let Some(time) = body.adventure_time {
-        draw_text_mut(
-            &mut blend,
-            color,
-            345,
-            369,
-            PX_34,
-            190,
-            &*TRAVITIA_FONT,
-            &time,
+        let shape = match &body.outline {
+            Some(outline) => dilate(&mask, outline.width),
+            None => mask,
+        };
+        let (origin_x, origin_y) = (
+            i64::from(x) - i64::from(pad),
+            i64::from(top) - i64::from(pad),
         );
-    }
 
-    if let Some((_, item_name, stat)) = body.right_hand_item {
-        draw_text_mut(
-            &mut blend,
-            color,
-            345,
-            135,
-            PX_52,
-            325,
-            &*TRAVITIA_FONT,
-            &item_name,
-        );
-        draw_text_mut(
-            &mut blend,
-            color,
-            720,
-            135,
-            PX_52,
-            85,
-            &*TRAVITIA_FONT,
-            &stat,
-        );
+        if let Some(shadow) = &body.shadow {
+            let shadow_color = shadow
+                .color
+                .map_or(Rgba([contrast[0], contrast[1], contrast[2], 191]), to_rgba);
+            let blurred = if shadow.blur > 0.0 {
+                imageops::blur(&shape, shadow.blur)
+            } else {
+                shape.clone()
+            };
+
+            fill_mask(
+                &mut canvas.0,
+                &blurred,
+                origin_x + i64::from(shadow.x),
+                origin_y + i64::from(shadow.y),
+                shadow_color,
+            );
+        }
+
+        if let Some(outline) = &body.outline {
+            let outline_color = outline.color.map_or(contrast, to_rgba);
+
+            fill_mask(&mut canvas.0, &shape, origin_x, origin_y, outline_color);
+        }
     }
 
-    if let Some((_, item_name, stat)) = body.left_hand_item {
-        draw_text_mut(
-            &mut blend,
-            color,
-            345,
-            206,
-            PX_52,
-            325,
-            &*TRAVITIA_FONT,
-            &item_name,
-        );
-        draw_text_mut(
-            &mut blend,
-            color,
-            720,
-            206,
-            PX_52,
-            85,
-            &*TRAVITIA_FONT,
-            &stat,
-        );
+    for (run, emoji) in runs.iter().zip(emojis) {
+        match (&run.kind, emoji) {
+            (RunKind::Text { font, text }, _) => draw_text_mut(
+                canvas,
+                color,
+                x + run.x,
+                y + run.y,
+                scale,
+                slot.max_width - run.x,
+                *font,
+                text,
+            ),
+            (RunKind::Emoji { .. }, Some(emoji)) => overlay(
+                &mut canvas.0,
+                &*emoji,
+                i64::from(x + run.x),
+                i64::from(y + run.y),
+            ),
+            (RunKind::Emoji { .. }, None) => {}
+        }
     }
+}
+
+/// Draws a rounded bar filled with a gradient up to its progress, with its
+/// label centered on top.
+fn draw_bar(canvas: &mut Blend<RgbaImage>, body: &ProfileJson, slot: &BarSlot, bar: &Bar) {
+    let (x, y) = (i64::from(slot.x), i64::from(slot.y));
+    let width = f64::from(slot.width);
+
+    fill_pill(&mut canvas.0, x, y, slot.width, slot.height, width, |_| {
+        BAR_TRACK
+    });
+
+    let end = bar.color.map_or(BAR_COLOR, to_rgba);
+    let start = end.map_without_alpha(|channel| (u16::from(channel) * 3 / 5) as u8);
+    let progress = (bar.current / bar.max).clamp(0.0, 1.0);
+
+    fill_pill(
+        &mut canvas.0,
+        x,
+        y,
+        slot.width,
+        slot.height,
+        progress * width,
+        |position| {
+            start.map2(&end, |from, to| {
+                f64::from(from)
+                    .mul_add(1.0 - position, f64::from(to) * position)
+                    .round() as u8
+            })
+        },
+    );
+
+    let label = bar
+        .label
+        .clone()
+        .unwrap_or_else(|| format!("{} / {}", bar.current, bar.max));
+    // Keeps the label clear of the rounded ends
+    let label_box = TextBox {
+        x: slot.x + slot.width as i32 / 2,
+        y: slot.y + slot.height as i32 / 2,
+        size: slot.size,
+        min_size: None,
+        max_width: slot.width.saturating_sub(slot.height) as i32,
+        font: slot.font,
+        align: Align::Center,
+        anchor: Anchor::Middle,
+    };
+
+    draw_slot(canvas, body, &label_box, &label);
+}
+
+pub async fn genprofile(
+    body: ProfileJson,
+    fetcher: Arc<Fetcher>,
+    pool: &RenderPool,
+    images: &ImageCache,
+) -> Result<Response<Body>> {
+    let icons = resolve(&body)?;
+
+    let background = if body.image == "0" {
+        None
+    } else {
+        Some(fetcher.fetch(&body.image).await?)
+    };
 
-    let final_image = encode_png(&blend.0)?;
+    let final_image = pool.run(move || render(&body, &icons, background)).await?;
 
     let tag = images.insert(final_image);
 
@@ -329,3 +625,85 @@ pub async fn genprofile(
         .header("content-type", "text/plain")
         .body(Body::from(tag))?)
 }
+
+fn render(body: &ProfileJson, icons: &Icons, background: Option<Bytes>) -> Result<Vec<u8>> {
+    let decoded = background
+        .map(|buf| METRICS.time_stage(ROUTE, "decode", || decode(&buf)))
+        .transpose()?;
+
+    let composite_start = Instant::now();
+
+    let mut img = if let Some(image) = decoded {
+        let image = image.into_rgba8();
+        let (width, height) = DEFAULT_PROFILE.dimensions();
+
+        if image.width() < width || image.height() < height {
+            return Err(Error::ImageTooSmall);
+        }
+
+        if image.dimensions() == (width, height) {
+            image
+        } else {
+            match body.fit {
+                BackgroundFit::Cover => {
+                    cover(&image, width, height, body.focus.unwrap_or((0.5, 0.5)))
+                }
+                BackgroundFit::Contain => contain_blurred(&image, width, height),
+                BackgroundFit::Fill => resize(&image, width, height, FilterType::Lanczos3),
+            }
+        }
+    } else {
+        DEFAULT_PROFILE.clone()
+    };
+
+    let layout = icons.layout;
+
+    for slot in &layout.icons {
+        if let Some(icon) = icons.get(slot.field) {
+            overlay(&mut img, icon, slot.x, slot.y);
+        }
+    }
+
+    let badge_width = icons.badges.iter().map(|badge| badge.width()).max();
+    let badge_height = icons.badges.iter().map(|badge| badge.height()).max();
+    let (scale, positions) = badge_layout(
+        &layout.badges,
+        icons.badges.len(),
+        badge_width.unwrap_or_default(),
+        badge_height.unwrap_or_default(),
+    );
+
+    for (badge, (x, y)) in icons.badges.iter().zip(positions) {
+        if scale < 1.0 {
+            let width = (f64::from(badge.width()) * scale).round() as u32;
+            let height = (f64::from(badge.height()) * scale).round() as u32;
+
+            overlay(
+                &mut img,
+                &resize(*badge, width.max(1), height.max(1), FilterType::Lanczos3),
+                x,
+                y,
+            );
+        } else {
+            overlay(&mut img, *badge, x, y);
+        }
+    }
+
+    let mut blend = Blend(img);
+
+    for slot in &layout.bars {
+        if let Some(bar) = body.bars.get(&slot.name) {
+            draw_bar(&mut blend, body, slot, bar);
+        }
+    }
+
+    for slot in &layout.texts {
+        if let Some(text) = body.text(slot.field) {
+            draw_slot(&mut blend, body, &slot.text, &text);
+        }
+    }
+
+    METRICS.observe_stage(ROUTE, "composite", composite_start.elapsed());
+
+    Ok(METRICS.time_stage(ROUTE, "encode", || encode_png(&blend.0))?)
+}
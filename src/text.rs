@@ -0,0 +1,218 @@
+use std::{borrow::Cow, iter, ops::Range};
+
+use ab_glyph::{Font, PxScale, ScaleFont};
+
+use crate::{constants::EMOJI, emoji::Sprite};
+
+/// Picks the first of `fonts` that has a glyph for `c`, or the first font if
+/// none of them has one.
+fn font_for<F: Font>(fonts: &[F], c: char) -> usize {
+    fonts
+        .iter()
+        .position(|font| font.glyph_id(c).0 != 0)
+        .unwrap_or(0)
+}
+
+enum Segment {
+    /// A character drawn with the font at this index.
+    Glyph(usize),
+    /// An emoji sprite.
+    Emoji(&'static Sprite),
+}
+
+/// Size of emoji sprites, which fill the line from the top down to the
+/// baseline of the first font.
+fn emoji_size<F: Font>(fonts: &[F], scale: PxScale) -> f32 {
+    fonts[0].as_scaled(scale).ascent()
+}
+
+/// Yields every character or emoji of `text` with its byte range, how it is
+/// drawn and how far it advances the pen, including kerning against the
+/// previous character.
+fn segments<'a, F: Font>(
+    fonts: &'a [F],
+    scale: PxScale,
+    text: &'a str,
+) -> impl Iterator<Item = (Range<usize>, Segment, f32)> + 'a {
+    let mut index = 0;
+    let mut previous = None;
+
+    iter::from_fn(move || {
+        let start = index;
+
+        if let Some((len, sprite)) = EMOJI.find(&text[start..]) {
+            index += len;
+            previous = None;
+
+            return Some((
+                start..index,
+                Segment::Emoji(sprite),
+                emoji_size(fonts, scale),
+            ));
+        }
+
+        let c = text[start..].chars().next()?;
+        index += c.len_utf8();
+
+        let font_index = font_for(fonts, c);
+        let font = fonts[font_index].as_scaled(scale);
+        let glyph = font.glyph_id(c);
+
+        let mut advance = font.h_advance(glyph);
+
+        // Glyphs of different fonts cannot be kerned against each other
+        if let Some((previous_index, previous_glyph)) = previous {
+            if previous_index == font_index {
+                advance += font.kern(previous_glyph, glyph);
+            }
+        }
+
+        previous = Some((font_index, glyph));
+
+        Some((start..index, Segment::Glyph(font_index), advance))
+    })
+}
+
+/// A part of a text that is drawn at once, positioned relative to the whole
+/// text.
+pub struct Run<'a, F> {
+    pub x: i32,
+    pub y: i32,
+    pub kind: RunKind<'a, F>,
+}
+
+pub enum RunKind<'a, F> {
+    /// Characters drawn with a single font.
+    Text { font: &'a F, text: &'a str },
+    /// An emoji sprite, see [`Sprite::render`].
+    Emoji { sprite: &'static Sprite, size: u32 },
+}
+
+/// Splits `text` into emoji sprites and runs of characters that are drawn
+/// with the same font.
+///
+/// Every character uses the first of `fonts` that has a glyph for it, with
+/// its baseline on the one of the first font.
+pub fn runs<'a, F: Font>(fonts: &'a [F], scale: PxScale, text: &'a str) -> Vec<Run<'a, F>> {
+    let ascent = |index: usize| fonts[index].as_scaled(scale).ascent();
+    let text_run = |font_index: usize, x: f32, range: Range<usize>| Run {
+        x: x as i32,
+        y: (ascent(0) - ascent(font_index)) as i32,
+        kind: RunKind::Text {
+            font: &fonts[font_index],
+            text: &text[range],
+        },
+    };
+
+    let mut runs = Vec::new();
+    // Font, start and position of the characters not added to a run yet
+    let mut pending: Option<(usize, usize, f32)> = None;
+    let mut x = 0.0;
+
+    for (range, segment, advance) in segments(fonts, scale, text) {
+        if let Some((font_index, start, start_x)) = pending {
+            if !matches!(segment, Segment::Glyph(index) if index == font_index) {
+                runs.push(text_run(font_index, start_x, start..range.start));
+                pending = None;
+            }
+        }
+
+        match segment {
+            Segment::Glyph(font_index) => {
+                pending.get_or_insert((font_index, range.start, x));
+            }
+            Segment::Emoji(sprite) => runs.push(Run {
+                x: x as i32,
+                y: 0,
+                kind: RunKind::Emoji {
+                    sprite,
+                    size: advance as u32,
+                },
+            }),
+        }
+
+        x += advance;
+    }
+
+    if let Some((font_index, start, start_x)) = pending {
+        runs.push(text_run(font_index, start_x, start..text.len()));
+    }
+
+    runs
+}
+
+/// Width of `text` in pixels when drawn with `fonts` at `scale`, including
+/// kerning.
+pub fn text_width<F: Font>(fonts: &[F], scale: PxScale, text: &str) -> f32 {
+    segments(fonts, scale, text)
+        .map(|(_, _, advance)| advance)
+        .sum()
+}
+
+/// Height of a line of text drawn with the first of `fonts` at `scale`.
+pub fn line_height<F: Font>(fonts: &[F], scale: PxScale) -> f32 {
+    let font = fonts[0].as_scaled(scale);
+
+    font.ascent() - font.descent()
+}
+
+/// Fits `text` into `max_width` pixels. The scale is reduced from `scale`
+/// down to `min_scale` first, and if the text is still too wide it is cut off
+/// with an ellipsis.
+///
+/// Returns the scale to draw with and the text to draw.
+pub fn fit_text<'a, F: Font>(
+    fonts: &[F],
+    scale: PxScale,
+    min_scale: PxScale,
+    max_width: i32,
+    text: &'a str,
+) -> (PxScale, Cow<'a, str>) {
+    #[allow(clippy::cast_precision_loss)]
+    let max_width = max_width as f32;
+    let width = text_width(fonts, scale, text);
+
+    if width <= max_width {
+        return (scale, Cow::Borrowed(text));
+    }
+
+    // Widths grow linearly with the scale
+    let factor = (max_width / width).max(min_scale.y / scale.y).min(1.0);
+    let scale = PxScale {
+        x: scale.x * factor,
+        y: scale.y * factor,
+    };
+
+    if text_width(fonts, scale, text) <= max_width {
+        return (scale, Cow::Borrowed(text));
+    }
+
+    (scale, Cow::Owned(ellipsize(fonts, scale, max_width, text)))
+}
+
+/// Cuts `text` off after as many characters as fit into `max_width` pixels
+/// together with an ellipsis.
+fn ellipsize<F: Font>(fonts: &[F], scale: PxScale, max_width: f32, text: &str) -> String {
+    // Fall back to dots if no font has a glyph for the ellipsis character
+    let ellipsis = if fonts.iter().any(|font| font.glyph_id('…').0 != 0) {
+        "…"
+    } else {
+        "..."
+    };
+    let available = max_width - text_width(fonts, scale, ellipsis);
+
+    let mut width = 0.0;
+    let mut end = 0;
+
+    for (range, _, advance) in segments(fonts, scale, text) {
+        width += advance;
+
+        if width > available {
+            break;
+        }
+
+        end = range.end;
+    }
+
+    format!("{}{ellipsis}", text[..end].trim_end())
+}